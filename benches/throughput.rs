@@ -0,0 +1,225 @@
+//! Encode & decode throughput across representative inputs and window sizes.
+//!
+//! Run with `cargo bench`. Throughput is reported in bytes per second of
+//! uncompressed data, and the compression ratio of each input is printed
+//! before its group runs.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use heatshrink::{decode, encode, encode_with_scratch, Config, FixedDecoder, FixedEncoder};
+use std::hint::black_box;
+
+const INPUT_SIZE: usize = 16 * 1024;
+const WINDOWS: [u8; 4] = [8, 11, 13, 15];
+const LOOKAHEAD: u8 = 4;
+
+const TEXT: &str = "It is a truth universally acknowledged, that a single man in \
+    possession of a good fortune, must be in want of a wife. However little known \
+    the feelings or views of such a man may be on his first entering a \
+    neighbourhood, this truth is so well fixed in the minds of the surrounding \
+    families, that he is considered as the rightful property of some one or \
+    other of their daughters. \"My dear Mr. Bennet,\" said his lady to him one \
+    day, \"have you heard that Netherfield Park is let at last?\" Mr. Bennet \
+    replied that he had not. \"But it is,\" returned she; \"for Mrs. Long has \
+    just been here, and she told me all about it.\" Mr. Bennet made no answer. ";
+
+fn random() -> Vec<u8> {
+    // Xorshift generator, so the input is the same for every run
+    let mut state = 0x2545_f491_u32;
+    (0..INPUT_SIZE)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+fn repetitive() -> Vec<u8> {
+    (0..INPUT_SIZE).map(|i| (i % 7 / 3) as u8).collect()
+}
+
+fn text() -> Vec<u8> {
+    TEXT.bytes().cycle().take(INPUT_SIZE).collect()
+}
+
+fn inputs() -> [(&'static str, Vec<u8>); 3] {
+    [
+        ("random", random()),
+        ("repetitive", repetitive()),
+        ("text", text()),
+    ]
+}
+
+fn bench_encode(c: &mut Criterion) {
+    let mut output = vec![0; 2 * INPUT_SIZE];
+    for (name, input) in inputs() {
+        let mut group = c.benchmark_group(format!("encode/{name}"));
+        group.throughput(Throughput::Bytes(input.len() as u64));
+        group.sample_size(10);
+        for window in WINDOWS {
+            let cfg = Config::new(window, LOOKAHEAD).unwrap();
+            let len = encode(&input, &mut output, &cfg).unwrap().len();
+            println!(
+                "{name} window {window}: ratio {:.3}",
+                len as f64 / input.len() as f64
+            );
+            group.bench_with_input(BenchmarkId::from_parameter(window), &input, |b, input| {
+                b.iter(|| encode(black_box(input), &mut output, &cfg).unwrap().len())
+            });
+        }
+        group.finish();
+    }
+}
+
+fn bench_scratch(c: &mut Criterion) {
+    let mut output = vec![0; 2 * INPUT_SIZE];
+    let mut scratch = vec![0; (1 << WINDOWS[3]) + 512];
+    for (name, input) in inputs() {
+        let mut group = c.benchmark_group(format!("encode_with_scratch/{name}"));
+        group.throughput(Throughput::Bytes(input.len() as u64));
+        group.sample_size(10);
+        for window in WINDOWS {
+            let cfg = Config::new(window, LOOKAHEAD).unwrap();
+            group.bench_with_input(BenchmarkId::from_parameter(window), &input, |b, input| {
+                b.iter(|| {
+                    encode_with_scratch(black_box(input), &mut output, &mut scratch, &cfg)
+                        .unwrap()
+                        .len()
+                })
+            });
+        }
+        group.finish();
+    }
+}
+
+fn bench_periodic(c: &mut Criterion) {
+    // A random block repeated, so that the longest match is found at every
+    // position, against many partial matches in large windows
+    let block = random();
+    let input: Vec<u8> = block[..200]
+        .iter()
+        .cycle()
+        .take(INPUT_SIZE)
+        .copied()
+        .collect();
+    let mut output = vec![0; 2 * INPUT_SIZE];
+    let mut group = c.benchmark_group("encode/periodic");
+    group.throughput(Throughput::Bytes(input.len() as u64));
+    group.sample_size(10);
+    for window in WINDOWS {
+        let cfg = Config::new(window, 6).unwrap();
+        group.bench_with_input(BenchmarkId::from_parameter(window), &input, |b, input| {
+            b.iter(|| encode(black_box(input), &mut output, &cfg).unwrap().len())
+        });
+    }
+    group.finish();
+}
+
+fn bench_long_matches(c: &mut Criterion) {
+    // Text with a large lookahead, so that most of the time goes into
+    // comparing long matches
+    let input = text();
+    let mut output = vec![0; 2 * INPUT_SIZE];
+    let mut group = c.benchmark_group("encode/long_matches");
+    group.throughput(Throughput::Bytes(input.len() as u64));
+    group.sample_size(10);
+    for lookahead in [4, 8, 10] {
+        let cfg = Config::new(11, lookahead).unwrap();
+        group.bench_with_input(
+            BenchmarkId::from_parameter(lookahead),
+            &input,
+            |b, input| b.iter(|| encode(black_box(input), &mut output, &cfg).unwrap().len()),
+        );
+    }
+    group.finish();
+}
+
+fn bench_decode(c: &mut Criterion) {
+    let mut compressed = vec![0; 2 * INPUT_SIZE];
+    let mut output = vec![0; INPUT_SIZE];
+    for (name, input) in inputs() {
+        let mut group = c.benchmark_group(format!("decode/{name}"));
+        group.throughput(Throughput::Bytes(input.len() as u64));
+        for window in WINDOWS {
+            let cfg = Config::new(window, LOOKAHEAD).unwrap();
+            let len = encode(&input, &mut compressed, &cfg).unwrap().len();
+            let compressed = &compressed[..len];
+            group.bench_with_input(
+                BenchmarkId::from_parameter(window),
+                compressed,
+                |b, compressed| {
+                    b.iter(|| {
+                        decode(black_box(compressed), &mut output, &cfg)
+                            .unwrap()
+                            .len()
+                    })
+                },
+            );
+        }
+        group.finish();
+    }
+}
+
+fn bench_zeros(c: &mut Criterion) {
+    let input = vec![0; 1 << 20];
+    let mut output = vec![0; 1 << 20];
+    let cfg = Config::default();
+    let mut group = c.benchmark_group("encode/zeros");
+    group.throughput(Throughput::Bytes(input.len() as u64));
+    group.sample_size(10);
+    group.bench_function("1MB", |b| {
+        b.iter(|| encode(black_box(&input), &mut output, &cfg).unwrap().len())
+    });
+    group.finish();
+}
+
+fn bench_fixed(c: &mut Criterion) {
+    // The fixed size codecs against the runtime config at the same sizes
+    let input = text();
+    let cfg = Config::new(11, LOOKAHEAD).unwrap();
+    let mut compressed = vec![0; 2 * INPUT_SIZE];
+    let mut output = vec![0; INPUT_SIZE];
+    let len = encode(&input, &mut compressed, &cfg).unwrap().len();
+    let compressed = &compressed[..len];
+    let mut group = c.benchmark_group("fixed");
+    group.throughput(Throughput::Bytes(input.len() as u64));
+    group.sample_size(10);
+    group.bench_function("encode", |b| {
+        b.iter(|| encode(black_box(&input), &mut output, &cfg).unwrap().len())
+    });
+    group.bench_function("FixedEncoder", |b| {
+        b.iter(|| {
+            FixedEncoder::<11, LOOKAHEAD>::encode(black_box(&input), &mut output)
+                .unwrap()
+                .len()
+        })
+    });
+    group.bench_function("decode", |b| {
+        b.iter(|| {
+            decode(black_box(compressed), &mut output, &cfg)
+                .unwrap()
+                .len()
+        })
+    });
+    group.bench_function("FixedDecoder", |b| {
+        b.iter(|| {
+            FixedDecoder::<11, LOOKAHEAD>::decode(black_box(compressed), &mut output)
+                .unwrap()
+                .len()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_encode,
+    bench_scratch,
+    bench_periodic,
+    bench_long_matches,
+    bench_decode,
+    bench_fixed,
+    bench_zeros
+);
+criterion_main!(benches);
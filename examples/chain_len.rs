@@ -0,0 +1,72 @@
+//! Compares compression ratio and time at several `max_chain_len` values.
+//!
+//! Usage: cargo run --release --example chain_len [FILE]
+//!
+//! Without a file argument a synthetic, moderately compressible input is used.
+
+use heatshrink::{encode_with_options, Config, EncodeOptions};
+use std::time::Instant;
+
+fn synthetic() -> Vec<u8> {
+    let words = [
+        "sensor",
+        "reading",
+        "value",
+        "timestamp",
+        "status",
+        "ok",
+        "error",
+        "voltage",
+        "current",
+        "temperature",
+        "0",
+        "1",
+        "42",
+        "3.3",
+        "\n",
+    ];
+    let mut state = 0x2545_f491_u32;
+    let mut data = Vec::new();
+    while data.len() < 256 * 1024 {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        data.extend_from_slice(words[state as usize % words.len()].as_bytes());
+        data.push(b' ');
+    }
+    data
+}
+
+fn main() {
+    let input = match std::env::args().nth(1) {
+        Some(path) => std::fs::read(path).expect("Failed to read input file"),
+        None => synthetic(),
+    };
+    let mut output = vec![0; input.len() * 9 / 8 + 16];
+    let cfg = Config::new(13, 5).unwrap();
+    println!("input: {} bytes, window 13, lookahead 5", input.len());
+    println!(
+        "{:>14} {:>10} {:>8} {:>10}",
+        "max_chain_len", "output", "ratio", "ms"
+    );
+    for max_chain_len in [1, 4, 16, 64, 256, usize::MAX] {
+        let opts = EncodeOptions::new().with_max_chain_len(max_chain_len);
+        let start = Instant::now();
+        let len = encode_with_options(&input, &mut output, &cfg, &opts)
+            .unwrap()
+            .len();
+        let elapsed = start.elapsed();
+        let label = if max_chain_len == usize::MAX {
+            "unlimited".to_string()
+        } else {
+            max_chain_len.to_string()
+        };
+        println!(
+            "{:>14} {:>10} {:>8.3} {:>10.1}",
+            label,
+            len,
+            len as f64 / input.len() as f64,
+            elapsed.as_secs_f64() * 1000.0
+        );
+    }
+}
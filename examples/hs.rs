@@ -0,0 +1,125 @@
+//! Command line tool mirroring the `heatshrink` tool of the C library, so
+//! that the output of both can be compared directly.
+//!
+//! Usage: cargo run --features std --example hs -- [-e|-d] [-v] [-w BITS] [-l BITS] [IN_FILE] [OUT_FILE]
+//!
+//! Compresses with `-e`, the default, or decompresses with `-d`, using a
+//! window of 2^`-w` bytes and a lookahead of 2^`-l` bytes, 11 and 4 unless
+//! given. Reads stdin and writes stdout when no files are given, or when
+//! a file is `-`. `-v` prints the sizes to stderr when done.
+//!
+//! The compressed output is identical to the C tool's, except where the C
+//! encoder refers to the zero filled window before the data, as in data
+//! holding runs of zeros. Both tools decode the output of either.
+
+use heatshrink::{Config, HeatshrinkReader, HeatshrinkWriter};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::process::exit;
+
+fn usage() -> ! {
+    eprintln!("Usage: hs [-h] [-e|-d] [-v] [-w BITS] [-l BITS] [IN_FILE] [OUT_FILE]");
+    exit(1);
+}
+
+fn bits(arg: Option<String>) -> u8 {
+    arg.and_then(|arg| arg.parse().ok())
+        .unwrap_or_else(|| usage())
+}
+
+fn open_input(path: Option<&str>) -> io::Result<Box<dyn Read>> {
+    Ok(match path {
+        None | Some("-") => Box::new(io::stdin().lock()),
+        Some(path) => Box::new(File::open(path)?),
+    })
+}
+
+fn open_output(path: Option<&str>) -> io::Result<Box<dyn Write>> {
+    Ok(match path {
+        None | Some("-") => Box::new(io::stdout().lock()),
+        Some(path) => Box::new(File::create(path)?),
+    })
+}
+
+// Counts the bytes passing through a reader or writer
+struct Counted<R> {
+    inner: R,
+    count: u64,
+}
+
+impl<R: Read> Read for Counted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+}
+
+impl<W: Write> Write for Counted<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn main() -> io::Result<()> {
+    let mut decode = false;
+    let mut verbose = false;
+    let mut window = 11;
+    let mut lookahead = 4;
+    let mut files = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-e" => decode = false,
+            "-d" => decode = true,
+            "-v" => verbose = true,
+            "-w" => window = bits(args.next()),
+            "-l" => lookahead = bits(args.next()),
+            "-h" => usage(),
+            _ if arg.starts_with('-') && arg != "-" => usage(),
+            _ => files.push(arg),
+        }
+    }
+    if files.len() > 2 {
+        usage();
+    }
+    let cfg = Config::new(window, lookahead).unwrap_or_else(|err| {
+        eprintln!("hs: {}", err);
+        exit(1);
+    });
+
+    let mut input = Counted {
+        inner: BufReader::new(open_input(files.first().map(String::as_str))?),
+        count: 0,
+    };
+    let output = BufWriter::new(open_output(files.get(1).map(String::as_str))?);
+    let written = if decode {
+        let mut output = output;
+        let mut reader = HeatshrinkReader::new(&mut input, &cfg);
+        let written = io::copy(&mut reader, &mut output)?;
+        output.flush()?;
+        written
+    } else {
+        let mut writer = HeatshrinkWriter::new(
+            Counted {
+                inner: output,
+                count: 0,
+            },
+            &cfg,
+        );
+        io::copy(&mut input, &mut writer)?;
+        let mut output = writer.finish()?;
+        output.flush()?;
+        output.count
+    };
+    if verbose {
+        eprintln!("{} -> {} bytes", input.count, written);
+    }
+    Ok(())
+}
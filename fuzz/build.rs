@@ -0,0 +1,16 @@
+fn main() {
+    // Compiles the C library for the c_interop target, from a checkout of
+    // https://github.com/atomicobject/heatshrink
+    #[cfg(feature = "c-ffi")]
+    {
+        println!("cargo:rerun-if-env-changed=HEATSHRINK_C_DIR");
+        let dir = std::env::var("HEATSHRINK_C_DIR")
+            .expect("HEATSHRINK_C_DIR must point to the C heatshrink sources");
+        let dir = std::path::Path::new(&dir);
+        cc::Build::new()
+            .file(dir.join("heatshrink_encoder.c"))
+            .file(dir.join("heatshrink_decoder.c"))
+            .include(dir)
+            .compile("heatshrink");
+    }
+}
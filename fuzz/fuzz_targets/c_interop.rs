@@ -0,0 +1,142 @@
+#![no_main]
+
+// Checks interoperability with the C library, built by `build.rs` from the
+// sources in HEATSHRINK_C_DIR:
+//
+//     HEATSHRINK_C_DIR=../heatshrink cargo fuzz run --features c-ffi c_interop
+//
+// Compat mode must produce the exact bytes of the C encoder, and the default
+// encoding must decode with the C decoder, as C output must decode with ours
+
+use heatshrink::*;
+use libfuzzer_sys::fuzz_target;
+use std::ffi::c_void;
+
+// The dynamically allocating API of heatshrink_encoder.h & heatshrink_decoder.h
+extern "C" {
+    fn heatshrink_encoder_alloc(window_sz2: u8, lookahead_sz2: u8) -> *mut c_void;
+    fn heatshrink_encoder_free(hse: *mut c_void);
+    fn heatshrink_encoder_sink(
+        hse: *mut c_void,
+        in_buf: *mut u8,
+        size: usize,
+        input_size: *mut usize,
+    ) -> i32;
+    fn heatshrink_encoder_poll(
+        hse: *mut c_void,
+        out_buf: *mut u8,
+        out_buf_size: usize,
+        output_size: *mut usize,
+    ) -> i32;
+    fn heatshrink_encoder_finish(hse: *mut c_void) -> i32;
+    fn heatshrink_decoder_alloc(
+        input_buffer_size: u16,
+        expansion_buffer_sz2: u8,
+        lookahead_sz2: u8,
+    ) -> *mut c_void;
+    fn heatshrink_decoder_free(hsd: *mut c_void);
+    fn heatshrink_decoder_sink(
+        hsd: *mut c_void,
+        in_buf: *mut u8,
+        size: usize,
+        input_size: *mut usize,
+    ) -> i32;
+    fn heatshrink_decoder_poll(
+        hsd: *mut c_void,
+        out_buf: *mut u8,
+        out_buf_size: usize,
+        output_size: *mut usize,
+    ) -> i32;
+    fn heatshrink_decoder_finish(hsd: *mut c_void) -> i32;
+}
+
+// Result codes shared by the encoder and decoder calls
+const POLL_EMPTY: i32 = 0;
+const FINISH_DONE: i32 = 0;
+
+type Poll = unsafe extern "C" fn(*mut c_void, *mut u8, usize, *mut usize) -> i32;
+
+// Polls `state` until it has no more output
+fn drain(state: *mut c_void, poll: Poll, out: &mut Vec<u8>) {
+    let mut buf = [0; 256];
+    loop {
+        let mut n = 0;
+        let res = unsafe { poll(state, buf.as_mut_ptr(), buf.len(), &mut n) };
+        assert!(res >= 0, "poll failed with {}", res);
+        out.extend_from_slice(&buf[..n]);
+        if res == POLL_EMPTY {
+            return;
+        }
+    }
+}
+
+// Runs `data` through the C encoder or decoder `state`, freeing it afterwards
+fn run(
+    state: *mut c_void,
+    mut data: &[u8],
+    sink: unsafe extern "C" fn(*mut c_void, *mut u8, usize, *mut usize) -> i32,
+    poll: Poll,
+    finish: unsafe extern "C" fn(*mut c_void) -> i32,
+) -> Vec<u8> {
+    assert!(!state.is_null());
+    let mut out = Vec::new();
+    while !data.is_empty() {
+        let mut n = 0;
+        // The C library takes the input as mutable, but only reads it
+        let res = unsafe { sink(state, data.as_ptr() as *mut u8, data.len(), &mut n) };
+        assert!(res >= 0, "sink failed with {}", res);
+        data = &data[n..];
+        drain(state, poll, &mut out);
+    }
+    while unsafe { finish(state) } != FINISH_DONE {
+        drain(state, poll, &mut out);
+    }
+    out
+}
+
+fn c_encode(data: &[u8], cfg: &Config) -> Vec<u8> {
+    let hse = unsafe { heatshrink_encoder_alloc(cfg.window_sz2(), cfg.lookahead_sz2()) };
+    let out = run(
+        hse,
+        data,
+        heatshrink_encoder_sink,
+        heatshrink_encoder_poll,
+        heatshrink_encoder_finish,
+    );
+    unsafe { heatshrink_encoder_free(hse) };
+    out
+}
+
+fn c_decode(data: &[u8], cfg: &Config) -> Vec<u8> {
+    let hsd = unsafe { heatshrink_decoder_alloc(256, cfg.window_sz2(), cfg.lookahead_sz2()) };
+    let out = run(
+        hsd,
+        data,
+        heatshrink_decoder_sink,
+        heatshrink_decoder_poll,
+        heatshrink_decoder_finish,
+    );
+    unsafe { heatshrink_decoder_free(hsd) };
+    out
+}
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&sizes, data)) = data.split_first() else {
+        return;
+    };
+    // Windows of 4 - 11 with lookaheads from 3, the smallest the C library takes
+    let window = 4 + (sizes & 7);
+    let lookahead = 3 + (sizes >> 3) % (window - 3);
+    let cfg = Config::new(window, lookahead).unwrap();
+    let mut out = vec![0; 2 * data.len() + 16];
+    let mut res = vec![0; data.len()];
+
+    let expected = c_encode(data, &cfg);
+    let opts = EncodeOptions::new().with_compat(true);
+    let compat = encode_with_options(data, &mut out, &cfg, &opts).unwrap();
+    assert_eq!(compat, expected);
+    assert_eq!(decode(&expected, &mut res, &cfg).unwrap(), data);
+
+    let encoded = encode(data, &mut out, &cfg).unwrap();
+    assert_eq!(c_decode(encoded, &cfg), data);
+});
@@ -0,0 +1,54 @@
+#![no_main]
+
+use heatshrink::*;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&seed, data)) = data.split_first() else {
+        return;
+    };
+    // Chunk sizes of 1 to 64 bytes, from a generator seeded by the first byte
+    let mut state = seed as u32 | 0x100;
+    let mut chunk = || {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        (state % 64 + 1) as usize
+    };
+    let cfg: Config = Default::default();
+    let sz = 2 * data.len() + 16;
+    let mut out = vec![0; sz];
+    let mut res = vec![0; sz];
+    let expected = encode(data, &mut out, &cfg).unwrap();
+    let mut buf = [0; 64];
+
+    let mut encoder = StreamEncoder::new(vec![0; (1 << 11) + (1 << 4)], &cfg);
+    let mut encoded = Vec::new();
+    let mut rest = data;
+    loop {
+        let n = chunk().min(rest.len());
+        rest = &rest[encoder.sink(&rest[..n])..];
+        if rest.is_empty() && encoder.finish() {
+            break;
+        }
+        let n = encoder.poll(&mut buf[..chunk()]);
+        encoded.extend_from_slice(&buf[..n]);
+    }
+    assert_eq!(encoded, expected);
+
+    let mut decoder = StreamDecoder::new(vec![0; 1 << 11], &cfg);
+    let mut decoded = Vec::new();
+    let mut rest = &encoded[..];
+    loop {
+        let n = chunk().min(rest.len());
+        rest = &rest[decoder.sink(&rest[..n])..];
+        let size = chunk();
+        let n = decoder.poll(&mut buf[..size]).unwrap();
+        decoded.extend_from_slice(&buf[..n]);
+        if rest.is_empty() && n < size {
+            break;
+        }
+    }
+    assert_eq!(decoded, data);
+    assert_eq!(decode(&encoded, &mut res, &cfg).unwrap(), data);
+});
@@ -0,0 +1,113 @@
+use super::{decode, encode, Config, DecodeError};
+use rayon::prelude::*;
+use std::vec;
+use std::vec::Vec;
+
+const LEN_SIZE: usize = 4;
+
+/// Splits `input` into blocks of `block_size` bytes, and compresses them in
+/// parallel. Each block starts with its uncompressed length as a little
+/// endian u32, followed by the compressed data.
+///
+/// Every block is compressed with an empty history, so that it can be
+/// decoded on its own, and a corrupted block does not affect the others.
+/// The price is a worse compression ratio, since matches can not reach
+/// into the previous block. With block sizes much larger than the window
+/// the loss is small, but for blocks of a few windows or less it is notable
+///
+/// # Panics
+///
+/// Panics if `block_size` is 0 or does not fit in a u32, or if `cfg` is invalid
+pub fn encode_blocks(input: &[u8], block_size: usize, cfg: &Config) -> Vec<Vec<u8>> {
+    assert!(block_size > 0 && u32::try_from(block_size).is_ok());
+    input
+        .par_chunks(block_size)
+        .map(|block| {
+            // Incompressible data grows by one bit per byte, plus padding
+            let mut out = vec![0; LEN_SIZE + block.len() + block.len() / 8 + 1];
+            let len = encode(block, &mut out[LEN_SIZE..], cfg)
+                .expect("Output is sized for the worst case, so only an invalid config fails")
+                .len();
+            out[..LEN_SIZE].copy_from_slice(&(block.len() as u32).to_le_bytes());
+            out.truncate(LEN_SIZE + len);
+            out
+        })
+        .collect()
+}
+
+/// Decompresses blocks produced by `encode_blocks()` in parallel, returning
+/// the result of each block separately. A block whose length prefix does not
+/// match the decompressed data results in `DecodeError::BadHeader`
+pub fn decode_blocks(blocks: &[Vec<u8>], cfg: &Config) -> Vec<Result<Vec<u8>, DecodeError>> {
+    blocks
+        .par_iter()
+        .map(|block| decode_block(block, cfg))
+        .collect()
+}
+
+fn decode_block(block: &[u8], cfg: &Config) -> Result<Vec<u8>, DecodeError> {
+    if block.len() < LEN_SIZE {
+        return Err(DecodeError::BadHeader);
+    }
+    let (len, data) = block.split_at(LEN_SIZE);
+    let len = u32::from_le_bytes(len.try_into().unwrap()) as usize;
+    // Reject lengths the data can not possibly expand to,
+    // before allocating memory for them
+    let backref_bits = 1 + cfg.window_sz2 as usize + cfg.lookahead_sz2 as usize;
+    let max_len = data.len() * 8 / backref_bits * (1 << cfg.lookahead_sz2 as usize);
+    if len > max_len {
+        return Err(DecodeError::BadHeader);
+    }
+    // One byte of slack detects data expanding beyond the recorded length
+    let mut out = vec![0; len + 1];
+    let decoded = decode(data, &mut out, cfg)
+        .map_err(|err| err.offset_input(LEN_SIZE))?
+        .len();
+    if decoded != len {
+        return Err(DecodeError::BadHeader);
+    }
+    out.truncate(len);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode_blocks, encode_blocks};
+    use crate::{Config, DecodeError};
+    use std::vec::Vec;
+
+    fn sample() -> Vec<u8> {
+        (0..50000u32).map(|i| (i * i % 251 % 13) as u8).collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let src = sample();
+        let cfg = Config::default();
+        for block_size in [1, 1000, 4096, 100000] {
+            let blocks = encode_blocks(&src, block_size, &cfg);
+            assert_eq!(blocks.len(), src.len().div_ceil(block_size));
+            let decoded: Vec<u8> = decode_blocks(&blocks, &cfg)
+                .into_iter()
+                .flat_map(|block| block.unwrap())
+                .collect();
+            assert_eq!(decoded, src);
+        }
+    }
+
+    #[test]
+    fn corrupt_block() {
+        let src = sample();
+        let cfg = Config::default();
+        let mut blocks = encode_blocks(&src, 10000, &cfg);
+        blocks[1][0] ^= 0x01;
+        blocks[3].truncate(2);
+        let decoded = decode_blocks(&blocks, &cfg);
+        for (i, block) in decoded.iter().enumerate() {
+            match i {
+                1 | 3 => assert!(matches!(block, Err(DecodeError::BadHeader))),
+                _ => assert_eq!(block.as_ref().unwrap()[..], src[i * 10000..][..10000]),
+            }
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use super::{decode, encode, Config, DecodeError, EncodeError};
+
+const CRC_SIZE: usize = 4;
+
+// CRC-32 (IEEE 802.3) lookup table for one nibble at a time,
+// trading some speed for a small flash footprint
+const CRC_TABLE: [u32; 16] = [
+    0x00000000, 0x1db71064, 0x3b6e20c8, 0x26d930ac, 0x76dc4190, 0x6b6b51f4, 0x4db26158, 0x5005713c,
+    0xedb88320, 0xf00f9344, 0xd6d6a3e8, 0xcb61b38c, 0x9b64c2b0, 0x86d3d2d4, 0xa00ae278, 0xbdbdf21c,
+];
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        crc = (crc >> 4) ^ CRC_TABLE[(crc & 0xf) as usize];
+        crc = (crc >> 4) ^ CRC_TABLE[(crc & 0xf) as usize];
+    }
+    !crc
+}
+
+/// Compression call like `encode()`, prepending a little endian CRC-32
+/// of the uncompressed data to the output
+pub fn encode_with_crc<'a>(
+    input: &[u8],
+    output: &'a mut [u8],
+    cfg: &Config,
+) -> Result<&'a [u8], EncodeError> {
+    if output.len() < CRC_SIZE {
+        return Err(EncodeError::OutputFull);
+    }
+    let (crc, rest) = output.split_at_mut(CRC_SIZE);
+    let len = encode(input, rest, cfg)?.len();
+    crc.copy_from_slice(&crc32(input).to_le_bytes());
+    Ok(&output[..CRC_SIZE + len])
+}
+
+/// Decompression call for data produced by `encode_with_crc()`. Returns
+/// `DecodeError::ChecksumMismatch` if the decompressed data does not match
+/// the stored CRC-32, or if the input is too short to hold one
+pub fn decode_with_crc<'a>(
+    input: &[u8],
+    output: &'a mut [u8],
+    cfg: &Config,
+) -> Result<&'a [u8], DecodeError> {
+    if input.len() < CRC_SIZE {
+        return Err(DecodeError::ChecksumMismatch);
+    }
+    let (crc, rest) = input.split_at(CRC_SIZE);
+    let decoded = decode(rest, output, cfg).map_err(|err| err.offset_input(CRC_SIZE))?;
+    if crc32(decoded).to_le_bytes() != crc {
+        return Err(DecodeError::ChecksumMismatch);
+    }
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{crc32, decode_with_crc, encode_with_crc};
+    use crate::{Config, DecodeError};
+
+    #[test]
+    fn check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn round_trip() {
+        let src = b"firmware config firmware config firmware config";
+        let cfg = Config::default();
+        let mut dst1 = [0; 100];
+        let mut dst2 = [0; 100];
+        let encoded = encode_with_crc(src, &mut dst1, &cfg).unwrap();
+        assert_eq!(encoded[..4], crc32(src).to_le_bytes());
+        let decoded = decode_with_crc(encoded, &mut dst2, &cfg).unwrap();
+        assert_eq!(decoded, src);
+    }
+
+    #[test]
+    fn corrupted() {
+        let src = b"firmware config firmware config firmware config";
+        let cfg = Config::default();
+        let mut dst1 = [0; 100];
+        let mut dst2 = [0; 100];
+        let len = encode_with_crc(src, &mut dst1, &cfg).unwrap().len();
+        dst1[6] ^= 0x10;
+        let res = decode_with_crc(&dst1[..len], &mut dst2, &cfg);
+        assert!(matches!(res, Err(DecodeError::ChecksumMismatch)));
+        let res = decode_with_crc(&dst1[..3], &mut dst2, &cfg);
+        assert!(matches!(res, Err(DecodeError::ChecksumMismatch)));
+    }
+}
@@ -1,7 +1,7 @@
-use super::Config;
+use super::{Config, Sizes};
 
 #[derive(Debug, Copy, Clone)]
-enum HSDstate {
+pub(crate) enum HSDstate {
     HSDSTagBit,          /* tag bit */
     HSDSYieldLiteral,    /* ready to yield literal byte */
     HSDSBackrefIndexMsb, /* most significant byte of index */
@@ -11,40 +11,709 @@ enum HSDstate {
     HSDSYieldBackref,    /* ready to yield back-reference */
     HSDSNeedMoreData,    /* End of input buffer detected */
     OutputFull,          /* Abort due to full output */
+    IllegalBackref,      /* Abort due to backref outside of the output */
+    ConfigMismatch,      /* Abort due to a stream looking like another config */
 }
 
+impl HSDstate {
+    // States ending the decoding loop
+    fn is_final(self) -> bool {
+        matches!(
+            self,
+            HSDstate::HSDSNeedMoreData
+                | HSDstate::OutputFull
+                | HSDstate::IllegalBackref
+                | HSDstate::ConfigMismatch
+        )
+    }
+
+    // Name of the state in the C library, as far as it has one
+    #[cfg(feature = "trace")]
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            HSDstate::HSDSTagBit => "HSDS_TAG_BIT",
+            HSDstate::HSDSYieldLiteral => "HSDS_YIELD_LITERAL",
+            HSDstate::HSDSBackrefIndexMsb => "HSDS_BACKREF_INDEX_MSB",
+            HSDstate::HSDSBackrefIndexLsb => "HSDS_BACKREF_INDEX_LSB",
+            HSDstate::HSDSBackrefCountMsb => "HSDS_BACKREF_COUNT_MSB",
+            HSDstate::HSDSBackrefCountLsb => "HSDS_BACKREF_COUNT_LSB",
+            HSDstate::HSDSYieldBackref => "HSDS_YIELD_BACKREF",
+            HSDstate::HSDSNeedMoreData => "NEED_MORE_DATA",
+            HSDstate::OutputFull => "OUTPUT_FULL",
+            HSDstate::IllegalBackref => "ILLEGAL_BACKREF",
+            HSDstate::ConfigMismatch => "CONFIG_MISMATCH",
+        }
+    }
+}
+
+/// Step of the decoder, as reported to the callback set with
+/// `DecodeOptions::with_trace()`. Positions count from the start of the
+/// input and output, also for the streaming decoder
+#[cfg(feature = "trace")]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DecodeEvent {
+    /// The decoder entered a state, named like in the C library, with
+    /// `bit_index` bits of the input read
+    State {
+        /// Name of the state, like `HSDS_TAG_BIT`
+        name: &'static str,
+        /// Number of input bits read
+        bit_index: usize,
+    },
+    /// A literal was decoded
+    Literal {
+        /// Position of the byte in the output
+        pos: usize,
+        /// The decoded byte
+        byte: u8,
+    },
+    /// A back-reference was decoded, which is yet to be checked and copied
+    Backref {
+        /// Position of the first copied byte in the output
+        pos: usize,
+        /// Distance back to the bytes copied
+        distance: usize,
+        /// Number of bytes copied
+        count: usize,
+    },
+}
+
+// Reports an event to the trace callback of the decoder options, if any.
+// Expands to nothing without the trace feature
+macro_rules! trace {
+    ($opts:expr, $event:expr) => {
+        #[cfg(feature = "trace")]
+        {
+            if let Some(trace) = $opts.trace {
+                trace($event);
+            }
+        }
+    };
+}
+pub(crate) use trace;
+
 /// Errors that can be encountered while decompressing data
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum DecodeError {
-    /// The output buffer was not large enough to hold the decompressed data
-    OutputFull,
+    /// The output buffer was not large enough to hold the decompressed data.
+    /// `output[..written]` holds the data decompressed before running out of space
+    OutputFull {
+        /// Number of valid bytes at the start of the output buffer
+        written: usize,
+        /// Position in the input of the symbol that did not fit, in bits
+        input_bit: usize,
+    },
+    /// A back-reference pointed before the start of the output, while the
+    /// zero filled window is disabled in `DecodeOptions`
+    IllegalBackref {
+        /// Position in the input of the back-reference, in bits
+        input_bit: usize,
+        /// Position in the output the back-reference was to be copied to
+        output_pos: usize,
+    },
+    /// The decompressed data did not match the stored checksum
+    ChecksumMismatch,
+    /// The frame header was missing, unknown, or held an invalid configuration
+    BadHeader,
+    /// The configuration is out of range, see `Config::new()`
+    InvalidConfig,
+    /// The input ended within a literal or back-reference, rather than in
+    /// the padding of the final byte. Only reported in strict mode, see
+    /// `DecodeOptions::with_strict()`
+    InputTooShort,
+    /// Many of the first back-references pointed before the start of the
+    /// output, which is what streams decoded with a different configuration
+    /// than they were compressed with tend to do. This is a heuristic, only
+    /// applied when enabled, see `DecodeOptions::with_mismatch_check()`
+    LikelyConfigMismatch,
+    /// The decompressed data exceeded the limit passed to `decode_limited()`
+    OutputLimitExceeded,
+}
+
+impl core::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let msg = match self {
+            DecodeError::OutputFull { .. } => "Output is full",
+            DecodeError::IllegalBackref { .. } => "Illegal back-reference",
+            DecodeError::ChecksumMismatch => "Checksum mismatch",
+            DecodeError::BadHeader => "Bad frame header",
+            DecodeError::InvalidConfig => "Invalid configuration",
+            DecodeError::InputTooShort => "Input ends within a symbol",
+            DecodeError::LikelyConfigMismatch => {
+                "Stream likely compressed with another configuration"
+            }
+            DecodeError::OutputLimitExceeded => "Output limit exceeded",
+        };
+        f.write_str(msg)
+    }
+}
+
+impl core::error::Error for DecodeError {}
+
+impl DecodeError {
+    // Shifts the input position of errors in a stream that starts `offset`
+    // bytes into the input, after a header
+    pub(crate) fn offset_input(self, offset: usize) -> Self {
+        match self {
+            DecodeError::OutputFull { written, input_bit } => DecodeError::OutputFull {
+                written,
+                input_bit: input_bit + offset * 8,
+            },
+            DecodeError::IllegalBackref {
+                input_bit,
+                output_pos,
+            } => DecodeError::IllegalBackref {
+                input_bit: input_bit + offset * 8,
+                output_pos,
+            },
+            err => err,
+        }
+    }
+}
+
+/// Statistics on the structure of a stream, as returned by `decode_with_stats()`
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct DecodeStats {
+    /// Number of literals decoded
+    pub literals: usize,
+    /// Number of back-references expanded
+    pub backrefs: usize,
+    /// Number of bytes copied by back-references
+    pub backref_bytes: usize,
+    /// Number of compressed bytes
+    pub input_len: usize,
+    /// Number of decompressed bytes
+    pub output_len: usize,
+}
+
+/// Tuning options for the decoder
+#[derive(Debug, Copy, Clone)]
+pub struct DecodeOptions {
+    pub(crate) zero_window: bool,
+    pub(crate) strict: bool,
+    pub(crate) mismatch_check: bool,
+    #[cfg(feature = "trace")]
+    pub(crate) trace: Option<fn(DecodeEvent)>,
+}
+
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        DecodeOptions {
+            zero_window: true,
+            strict: false,
+            mismatch_check: false,
+            #[cfg(feature = "trace")]
+            trace: None,
+        }
+    }
 }
 
-pub struct HeatshrinkDecoder<'a, 'b> {
+impl DecodeOptions {
+    /// Creates the default options
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Controls whether back-references may point into the zero filled window
+    /// preceding the output, like the C decoder allows and the C encoder
+    /// makes use of. Enabled by default. When disabled such back-references
+    /// are rejected with `DecodeError::IllegalBackref`, which is useful for
+    /// detecting corrupted streams produced by encoders that never emit them
+    pub fn with_zero_window(mut self, zero_window: bool) -> Self {
+        self.zero_window = zero_window;
+        self
+    }
+
+    /// Controls whether input ending within a literal or back-reference is
+    /// rejected with `DecodeError::InputTooShort`. Disabled by default, in
+    /// which case the incomplete symbol is dropped, and the data decoded
+    /// before it is returned. The padding of the final byte is always
+    /// shorter than 8 bits, so a longer incomplete symbol means that the
+    /// input was truncated, while shorter ones look like padding and are
+    /// not detected. Only the one-shot decoder checks this, as the
+    /// streaming decoder can not tell where the input ends
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Controls whether streams that look like they were compressed with a
+    /// different configuration are rejected with
+    /// `DecodeError::LikelyConfigMismatch`, for diagnosing streams that fail
+    /// to decode. Disabled by default. Such streams desync within a few
+    /// symbols, after which many back-references point before the start of
+    /// the output, into the zero filled window, so the stream is rejected
+    /// when at least 8 of the back-references in the first 256 output bytes,
+    /// and more than a quarter of them, do. The check catches most
+    /// mismatches, though not all, and also rejects valid streams: the C
+    /// encoder and compat mode match zero runs in the zero filled window,
+    /// which data with zero padded records, like binary tables, is full of
+    pub fn with_mismatch_check(mut self, mismatch_check: bool) -> Self {
+        self.mismatch_check = mismatch_check;
+        self
+    }
+
+    /// Sets a callback that is called on every state transition, literal,
+    /// and back-reference, for following the decoder step by step, like
+    /// when comparing against the C library. Both the one-shot and the
+    /// streaming decoder report their steps
+    #[cfg(feature = "trace")]
+    pub fn with_trace(mut self, trace: fn(DecodeEvent)) -> Self {
+        self.trace = Some(trace);
+        self
+    }
+}
+
+// Only the start of the output is checked for a config mismatch, as a
+// mismatched stream desyncs right away
+const MISMATCH_SPAN: usize = 256;
+const MISMATCH_REFS: usize = 8;
+
+// Back-reference counts for `DecodeOptions::with_mismatch_check()`
+#[derive(Debug, Default, Copy, Clone)]
+pub(crate) struct MismatchCheck {
+    backrefs: usize,
+    before_start: usize,
+}
+
+impl MismatchCheck {
+    // Records a backref at output position `pos`, returning true once the
+    // stream looks like it was compressed with another configuration
+    pub(crate) fn backref(&mut self, pos: usize, before_start: bool) -> bool {
+        if pos >= MISMATCH_SPAN {
+            return false;
+        }
+        self.backrefs += 1;
+        self.before_start += before_start as usize;
+        self.before_start >= MISMATCH_REFS && self.before_start * 4 > self.backrefs
+    }
+}
+
+/// One-shot decoder state, for callers that want to hold on to the decoder,
+/// like when embedding the codec in a custom protocol. `decode()` and its
+/// siblings are the simpler interface to the same decoder
+pub struct HeatshrinkDecoder<'a, 'b, C = Config> {
     output_count: u16,
-    output_index: u16,
+    output_index: usize, // Wide enough for the 65536 byte distance of a 16 bit window
     state: HSDstate,
-    head_index: usize, // Output position
-    bit_index: usize,  // Input index
-    cfg: Config,
+    head_index: usize,   // Output position
+    bit_index: usize,    // Input index
+    symbol_start: usize, // Input index of the symbol being decoded
+    stats: DecodeStats,
+    mismatch: MismatchCheck,
+    cfg: C,
+    opts: DecodeOptions,
+    dict: &'a [u8],
     input: &'a [u8],
+    in_place: Option<usize>, // Start of the input within the output, when decoding in place
     output: &'b mut [u8],
 }
 
 /// Basic decompression call. Source and destination must reside in memory,
 /// and destination must be large enough to hold the decompressed data,
-/// or an error will be returned
+/// or an error will be returned. The destination doubles as the window that
+/// back-references are resolved against, so no further memory is needed.
+/// Empty input decompresses to empty output.
+///
+/// Only the returned prefix of the destination is written, also when an
+/// error is returned, in which case `OutputFull` tells its length. The rest
+/// is left untouched, so a large destination needs no clearing beforehand,
+/// but it must be initialized, as decoding into `MaybeUninit` memory would
+/// need unsafe code
 pub fn decode<'a>(
     input: &[u8],
     output: &'a mut [u8],
     cfg: &Config,
 ) -> Result<&'a [u8], DecodeError> {
-    let decoder = HeatshrinkDecoder::new(input, output, cfg);
-    decoder.decode()
+    decode_with_options(input, output, cfg, &Default::default())
+}
+
+/// Decompression call like `decode()`, returning the number of bytes
+/// written to the start of `output` rather than borrowing them, for callers
+/// that own the buffer anyway, like behind a C interface. `decoded_len()`
+/// tells the length without decoding
+pub fn decode_len(input: &[u8], output: &mut [u8], cfg: &Config) -> Result<usize, DecodeError> {
+    decode(input, output, cfg).map(|decoded| decoded.len())
+}
+
+/// Decompression call like `decode()`, using the given decoder options
+pub fn decode_with_options<'a>(
+    input: &[u8],
+    output: &'a mut [u8],
+    cfg: &Config,
+    opts: &DecodeOptions,
+) -> Result<&'a [u8], DecodeError> {
+    decode_sized(input, output, *cfg, opts)
+}
+
+// Decompresses like `decode_with_options()`, for sizes that may be constants
+pub(crate) fn decode_sized<'a, C: Sizes>(
+    input: &[u8],
+    output: &'a mut [u8],
+    cfg: C,
+    opts: &DecodeOptions,
+) -> Result<&'a [u8], DecodeError> {
+    let mut decoder = HeatshrinkDecoder::with_sizes(input, output, cfg, opts);
+    let len = decoder.run()?;
+    Ok(&decoder.output[..len])
+}
+
+/// Decompression call like `decode()`, producing at most `max_output`
+/// bytes however large `output` is, to cap how far untrusted data may
+/// expand. Data decompressing to more results in
+/// `DecodeError::OutputLimitExceeded`, while an output buffer shorter
+/// than the limit still results in `DecodeError::OutputFull`
+pub fn decode_limited<'a>(
+    input: &[u8],
+    output: &'a mut [u8],
+    max_output: usize,
+    cfg: &Config,
+) -> Result<&'a [u8], DecodeError> {
+    let limited = max_output < output.len();
+    let len = output.len().min(max_output);
+    match decode(input, &mut output[..len], cfg) {
+        Err(DecodeError::OutputFull { .. }) if limited => Err(DecodeError::OutputLimitExceeded),
+        res => res,
+    }
+}
+
+/// Decompression call like `decode()`, additionally returning statistics
+/// on the literals and back-references the stream is made of. A stream
+/// without back-references, for instance, points to an encoder that
+/// does not search for matches
+pub fn decode_with_stats<'a>(
+    input: &[u8],
+    output: &'a mut [u8],
+    cfg: &Config,
+) -> Result<(&'a [u8], DecodeStats), DecodeError> {
+    let mut decoder = HeatshrinkDecoder::new(input, output, cfg);
+    let len = decoder.run()?;
+    let stats = DecodeStats {
+        input_len: input.len(),
+        output_len: len,
+        ..decoder.stats
+    };
+    Ok((&decoder.output[..len], stats))
+}
+
+/// Checks that `input` is a well formed stream, returning the length of
+/// the decompressed data without producing it. This is much cheaper than
+/// decompressing, and needs no memory for the output or a window, as the
+/// bytes themselves never make a stream invalid. Truncated input is
+/// reported as `DecodeError::InputTooShort` like in strict mode, and
+/// otherwise this succeeds exactly if the data decodes without error
+pub fn validate(input: &[u8], cfg: &Config) -> Result<usize, DecodeError> {
+    let strict = DecodeOptions::new().with_strict(true);
+    validate_with_options(input, cfg, &strict)
+}
+
+/// Checks a stream like `validate()`, against the given decoder options.
+/// Back-references before the start of the data are rejected with
+/// `DecodeError::IllegalBackref` when the zero window is disabled, and
+/// truncation is only detected in strict mode, like when decompressing
+pub fn validate_with_options(
+    input: &[u8],
+    cfg: &Config,
+    opts: &DecodeOptions,
+) -> Result<usize, DecodeError> {
+    if cfg.validate().is_err() {
+        return Err(DecodeError::InvalidConfig);
+    }
+    let mut decoder = HeatshrinkDecoder::with_options(input, &mut [], cfg, opts);
+    let mut check = MismatchCheck::default();
+    let mut len = 0;
+    loop {
+        decoder.symbol_start = decoder.bit_index;
+        let symbol = match decoder.get_bits(1) {
+            Some(0) => decoder.get_bits(cfg.window_sz2).and_then(|index| {
+                let count = decoder.get_bits(cfg.lookahead_sz2)?;
+                Some((index as usize + 1, count as usize + 1))
+            }),
+            Some(_) => decoder.get_bits(8).map(|_| (0, 1)),
+            None => None,
+        };
+        match symbol {
+            Some((0, count)) => len += count,
+            Some((dist, count)) => {
+                let before_start = dist > len;
+                if before_start && !opts.zero_window {
+                    return Err(DecodeError::IllegalBackref {
+                        input_bit: decoder.symbol_start,
+                        output_pos: len,
+                    });
+                }
+                if opts.mismatch_check && check.backref(len, before_start) {
+                    return Err(DecodeError::LikelyConfigMismatch);
+                }
+                len += count;
+            }
+            None => break,
+        }
+    }
+    if opts.strict && input.len() * 8 - decoder.symbol_start >= 8 {
+        return Err(DecodeError::InputTooShort);
+    }
+    Ok(len)
+}
+
+/// Returns the length `decode()` would decompress `input` to, or the error
+/// it would fail with, without needing the output or a window. This lets
+/// callers size or reject the destination before decoding, rather than
+/// trying and getting `DecodeError::OutputFull`. Unlike `validate()`,
+/// truncated input is accepted like `decode()` does, and
+/// `validate_with_options()` takes other decoder options, like rejecting
+/// back-references before the start with `DecodeError::IllegalBackref`
+pub fn decoded_len(input: &[u8], cfg: &Config) -> Result<usize, DecodeError> {
+    validate_with_options(input, cfg, &DecodeOptions::new())
+}
+
+/// Guesses whether `data` is already a heatshrink stream, so that storage
+/// layers receiving mixed data can avoid compressing it twice. This is a
+/// heuristic, which may be wrong both ways, and data crafted to be taken
+/// for a stream will be. The data has to pass `validate_with_options()`
+/// with back-references before its start and likely config mismatches
+/// rejected, which text and binaries tried fail within the first bytes,
+/// and then either decompress to at least twice its size, or have the
+/// byte entropy of compressed data. The latter catches literals of low
+/// entropy data that happen to form a valid stream, like all 0xff bytes,
+/// which actual compression would have turned into back-references. As
+/// the order 2 (collision) entropy of streams from text and binaries is
+/// above 5.5 bits per byte, at least 5 is required. Empty data is not
+/// taken for a stream
+pub fn looks_compressed(data: &[u8], cfg: &Config) -> bool {
+    let opts = DecodeOptions::new()
+        .with_strict(true)
+        .with_zero_window(false)
+        .with_mismatch_check(true);
+    let len = match validate_with_options(data, cfg, &opts) {
+        Ok(len) if !data.is_empty() => len,
+        _ => return false,
+    };
+    if len >= 2 * data.len() {
+        return true;
+    }
+    let mut counts = [0u64; 256];
+    for &b in data {
+        counts[b as usize] += 1;
+    }
+    // The chance of two bytes being equal, at most 2^-5
+    let collisions: u64 = counts.iter().map(|&c| c * c.saturating_sub(1)).sum();
+    let n = data.len() as u64;
+    collisions * 32 <= n * (n - 1)
+}
+
+/// Decompresses a continuation frame, treating `output[..history_len]` as
+/// previously decoded data that back-references may point into. Decoding
+/// starts at `output[history_len]`, and only the newly decoded data is returned.
+/// This allows decoding a large stream block by block, by keeping the tail
+/// of the previous block as history. A `history_len` beyond the end of
+/// `output` is reported as `DecodeError::OutputFull`
+pub fn decode_with_history<'a>(
+    input: &[u8],
+    output: &'a mut [u8],
+    history_len: usize,
+    cfg: &Config,
+) -> Result<&'a [u8], DecodeError> {
+    if history_len > output.len() {
+        return Err(DecodeError::OutputFull {
+            written: output.len(),
+            input_bit: 0,
+        });
+    }
+    let mut decoder = HeatshrinkDecoder::new(input, output, cfg);
+    decoder.head_index = history_len;
+    let len = decoder.run()?;
+    Ok(&decoder.output[history_len..len])
+}
+
+/// Decompression call allocating the output, so that the size of the
+/// decompressed data need not be known. Decoding starts over in a buffer
+/// of twice the size whenever the buffer is full, starting at twice the
+/// input size, so the data is decoded about twice on average
+#[cfg(feature = "alloc")]
+pub fn decode_owned(input: &[u8], cfg: &Config) -> Result<alloc::vec::Vec<u8>, DecodeError> {
+    let mut output = alloc::vec![0; (input.len() * 2).max(64)];
+    loop {
+        match decode(input, &mut output, cfg) {
+            Ok(decoded) => {
+                let len = decoded.len();
+                output.truncate(len);
+                return Ok(output);
+            }
+            Err(DecodeError::OutputFull { .. }) => output.resize(output.len() * 2, 0),
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Decompression call returning the decompressed data in a
+/// `heapless::Vec` holding up to `N` bytes, so that no allocator is needed.
+/// Output that does not fit results in `DecodeError::OutputFull`
+#[cfg(feature = "heapless")]
+pub fn decode_heapless<const N: usize>(
+    input: &[u8],
+    cfg: &Config,
+) -> Result<heapless::Vec<u8, N>, DecodeError> {
+    let mut output = heapless::Vec::new();
+    // Can not fail, as the length is the capacity
+    let _ = output.resize(N, 0);
+    let len = decode(input, &mut output, cfg)?.len();
+    output.truncate(len);
+    Ok(output)
+}
+
+/// Decompresses a frame into `output[start..]`, returning the end offset
+/// of the decompressed data. This allows decoding several independent
+/// frames one after the other into one buffer. Unlike with
+/// `decode_with_history()`, the frame can not refer to `output[..start]`,
+/// which is left untouched, while back-references before the start of the
+/// frame see the zero filled window like with `decode()`. In case of
+/// `OutputFull`, `written` counts from the start of `output` as well
+pub fn decode_into(
+    input: &[u8],
+    output: &mut [u8],
+    start: usize,
+    cfg: &Config,
+) -> Result<usize, DecodeError> {
+    let frame = match output.get_mut(start..) {
+        Some(frame) => frame,
+        None => {
+            return Err(DecodeError::OutputFull {
+                written: start,
+                input_bit: 0,
+            })
+        }
+    };
+    match decode(input, frame, cfg) {
+        Ok(decoded) => Ok(start + decoded.len()),
+        Err(DecodeError::OutputFull { written, input_bit }) => Err(DecodeError::OutputFull {
+            written: start + written,
+            input_bit,
+        }),
+        Err(err) => Err(err),
+    }
+}
+
+/// Decompresses the `compressed_len` bytes at the start of `buf` into the
+/// same buffer, for firmware updates and the like, where the compressed and
+/// the decompressed data do not both fit in memory. The compressed data is
+/// moved to the end of `buf` first, and decoded towards the front, which
+/// works as long as the output stays behind the input still to be read.
+/// `in_place_len()` returns the buffer size needed for a stream. Data that
+/// compresses evenly needs a few bytes more than the decompressed size,
+/// while data compressing much better at its start than at its end needs
+/// more, up to the decompressed plus the compressed size. A smaller buffer
+/// results in `DecodeError::OutputFull`, checked before `buf` is changed.
+/// Otherwise this decodes exactly like `decode()`
+///
+/// # Panics
+///
+/// Panics if `compressed_len` is larger than `buf.len()`
+pub fn decode_in_place<'a>(
+    buf: &'a mut [u8],
+    compressed_len: usize,
+    cfg: &Config,
+) -> Result<&'a [u8], DecodeError> {
+    assert!(
+        compressed_len <= buf.len(),
+        "Compressed data beyond the buffer"
+    );
+    // This also rejects invalid configs
+    if in_place_len(&buf[..compressed_len], cfg)? > buf.len() {
+        return Err(DecodeError::OutputFull {
+            written: 0,
+            input_bit: 0,
+        });
+    }
+    let start = buf.len() - compressed_len;
+    buf.copy_within(..compressed_len, start);
+    // With the room checked, the output never reaches the byte holding the
+    // next unread bit
+    let mut decoder = HeatshrinkDecoder::new(&[], buf, cfg);
+    decoder.in_place = Some(start);
+    let len = decoder.run()?;
+    Ok(&decoder.output[..len])
+}
+
+/// Returns the buffer size `decode_in_place()` needs for `input`: the
+/// decompressed size, plus the room needed to keep the output behind the
+/// input still to be read, which depends on how evenly the data compresses
+pub fn in_place_len(input: &[u8], cfg: &Config) -> Result<usize, DecodeError> {
+    if cfg.validate().is_err() {
+        return Err(DecodeError::InvalidConfig);
+    }
+    let mut decoder = HeatshrinkDecoder::new(input, &mut [], cfg);
+    let mut len = 0;
+    // Largest lead of the output over the input read, at any symbol
+    let mut lead = 0;
+    loop {
+        let count = match decoder.get_bits(1) {
+            Some(0) => decoder.get_bits(cfg.window_sz2).and_then(|_| {
+                let count = decoder.get_bits(cfg.lookahead_sz2)?;
+                Some(count as usize + 1)
+            }),
+            Some(_) => decoder.get_bits(8).map(|_| 1),
+            None => None,
+        };
+        match count {
+            Some(count) => len += count,
+            None => break,
+        }
+        // The output must end before the byte holding the next unread bit
+        lead = lead.max(len.saturating_sub(decoder.bit_index / 8));
+    }
+    Ok(input.len() + lead)
+}
+
+/// Decompression call for data produced by `encode_with_dict()`, with the
+/// window preset to the same dictionary `dict`
+pub fn decode_with_dict<'a>(
+    input: &[u8],
+    output: &'a mut [u8],
+    dict: &[u8],
+    cfg: &Config,
+) -> Result<&'a [u8], DecodeError> {
+    let mut decoder = HeatshrinkDecoder::new(input, output, cfg);
+    decoder.dict = dict;
+    let len = decoder.run()?;
+    Ok(&decoder.output[..len])
 }
 
 impl<'a, 'b> HeatshrinkDecoder<'a, 'b> {
-    fn new(input: &'a [u8], output: &'b mut [u8], cfg: &Config) -> Self {
+    /// Creates a decoder decompressing `input` into `output`
+    pub fn new(input: &'a [u8], output: &'b mut [u8], cfg: &Config) -> Self {
+        HeatshrinkDecoder::with_options(input, output, cfg, &Default::default())
+    }
+
+    /// Creates a decoder like `new()`, using the given decoder options
+    pub fn with_options(
+        input: &'a [u8],
+        output: &'b mut [u8],
+        cfg: &Config,
+        opts: &DecodeOptions,
+    ) -> Self {
+        HeatshrinkDecoder::with_sizes(input, output, *cfg, opts)
+    }
+
+    /// Decompresses all of the input, returning the decompressed data.
+    /// Calling it again returns the same result
+    pub fn decode(&mut self) -> Result<&[u8], DecodeError> {
+        let len = self.run()?;
+        Ok(&self.output[..len])
+    }
+
+    /// Returns the position in the input up to which data has been
+    /// decoded, in bits
+    pub fn bit_index(&self) -> usize {
+        self.bit_index
+    }
+}
+
+// The sizes are bound per method rather than on the impl, as the trait
+// is private to the crate
+impl<'a, 'b, C> HeatshrinkDecoder<'a, 'b, C> {
+    // Creates a decoder for sizes that need not come from a `Config`
+    fn with_sizes(input: &'a [u8], output: &'b mut [u8], cfg: C, opts: &DecodeOptions) -> Self {
         let output_count = 0;
         let output_index = 0;
         let head_index = 0;
@@ -56,17 +725,31 @@ impl<'a, 'b> HeatshrinkDecoder<'a, 'b> {
             head_index,
             state,
             bit_index,
-            cfg: *cfg,
+            symbol_start: 0,
+            stats: Default::default(),
+            mismatch: Default::default(),
+            cfg,
+            opts: *opts,
+            dict: &[],
             input,
+            in_place: None,
             output,
         }
     }
 
-    fn decode(mut self) -> Result<&'b [u8], DecodeError> {
+    fn run(&mut self) -> Result<usize, DecodeError>
+    where
+        C: Sizes,
+    {
+        // Configs made with new_unchecked() would break the bit arithmetic
+        if self.cfg.config().validate().is_err() {
+            return Err(DecodeError::InvalidConfig);
+        }
+        // Each state either consumes input bits, produces output, or moves to
+        // a state ending the loop, so the loop runs at most once per input
+        // bit and output byte, whatever the input
         loop {
-            if self.output.len() <= self.head_index {
-                return Err(DecodeError::OutputFull);
-            }
+            let progress = (self.bit_index, self.head_index);
             self.state = match self.state {
                 HSDstate::HSDSTagBit => self.st_tag_bit(),
                 HSDstate::HSDSYieldLiteral => self.st_yield_literal(),
@@ -76,31 +759,69 @@ impl<'a, 'b> HeatshrinkDecoder<'a, 'b> {
                 HSDstate::HSDSBackrefCountLsb => self.st_backref_count_lsb(),
                 HSDstate::HSDSYieldBackref => self.st_yield_backref(),
                 HSDstate::HSDSNeedMoreData => {
+                    if self.opts.strict && self.input().len() * 8 - self.symbol_start >= 8 {
+                        return Err(DecodeError::InputTooShort);
+                    }
                     break;
                 }
                 HSDstate::OutputFull => {
-                    return Err(DecodeError::OutputFull);
+                    return Err(DecodeError::OutputFull {
+                        written: self.head_index,
+                        input_bit: self.symbol_start,
+                    });
+                }
+                HSDstate::IllegalBackref => {
+                    return Err(DecodeError::IllegalBackref {
+                        input_bit: self.symbol_start,
+                        output_pos: self.head_index,
+                    });
+                }
+                HSDstate::ConfigMismatch => {
+                    return Err(DecodeError::LikelyConfigMismatch);
                 }
             };
-            // println!("State: {:?} {:?}", self.state, self.bit_index);
-            if self.input.len() * 8 < self.bit_index {
-                break;
-            }
+            trace!(
+                self.opts,
+                DecodeEvent::State {
+                    name: self.state.name(),
+                    bit_index: self.bit_index,
+                }
+            );
+            // get_bits() never advances past the end of the input, so the loop
+            // ends once a state finds too few bits left, which are padding
+            debug_assert!(
+                self.state.is_final() || (self.bit_index, self.head_index) != progress,
+                "no progress in {:?}",
+                self.state
+            );
+        }
+        Ok(self.head_index)
+    }
+
+    // The input, which lies at the end of the output when decoding in place
+    fn input(&self) -> &[u8] {
+        match self.in_place {
+            Some(start) => &self.output[start..],
+            None => self.input,
         }
-        Ok(&self.output[..self.head_index])
     }
 
     fn get_bits(&mut self, count: u8) -> Option<u16> {
+        let input = self.input();
         let end_pos = self.bit_index + count as usize;
-        if end_pos > self.input.len() * 8 {
+        if end_pos > input.len() * 8 {
             return None;
         }
+        // Bytes are read up to the one holding the last bit, at index
+        // (end_pos - 1) / 8, which the check above keeps within the input.
+        // The callers never read 0 bits
+        let mut pos = self.bit_index / 8;
         let mut num = 8 - (self.bit_index % 8);
-        let mut bitbuf = self.input[self.bit_index / 8] as u32;
+        let mut bitbuf = input[pos] as u32;
         let count = count as usize;
         while num < count {
-            self.bit_index += 8;
-            bitbuf = (bitbuf << 8) | self.input[self.bit_index / 8] as u32;
+            pos += 1;
+            bitbuf = (bitbuf << 8) | input[pos] as u32;
             num += 8;
         }
         bitbuf >>= num - count;
@@ -109,10 +830,14 @@ impl<'a, 'b> HeatshrinkDecoder<'a, 'b> {
         Some(bitbuf as u16)
     }
 
-    fn st_tag_bit(&mut self) -> HSDstate {
+    fn st_tag_bit(&mut self) -> HSDstate
+    where
+        C: Sizes,
+    {
+        self.symbol_start = self.bit_index;
         match self.get_bits(1) {
             Some(0) => {
-                if self.cfg.window_sz2 > 8 {
+                if self.cfg.window_sz2() > 8 {
                     HSDstate::HSDSBackrefIndexMsb
                 } else {
                     self.output_index = 0;
@@ -131,15 +856,74 @@ impl<'a, 'b> HeatshrinkDecoder<'a, 'b> {
                 return HSDstate::HSDSNeedMoreData;
             }
         };
+        // Only a literal beyond the output is an error, so that the output
+        // may be exactly as long as the decompressed data
+        if self.head_index == self.output.len() {
+            return HSDstate::OutputFull;
+        }
+        trace!(
+            self.opts,
+            DecodeEvent::Literal {
+                pos: self.head_index,
+                byte: byte as u8,
+            }
+        );
         self.output[self.head_index] = byte as u8;
         self.head_index += 1;
+        self.stats.literals += 1;
+        self.literal_run();
         HSDstate::HSDSTagBit
     }
 
-    fn st_backref_index_msb(&mut self) -> HSDstate {
-        let bit_ct = self.cfg.window_sz2 - 8;
+    // Fast path for runs of literals, as found in incompressible data.
+    // A tag bit and its literal are read together as one 9 bit code, which
+    // always lies within 2 input bytes, so that no state transitions are
+    // needed. Stops without consuming anything at the first backref tag
+    fn literal_run(&mut self) {
+        let input_bits = self.input().len() * 8;
+        while self.head_index < self.output.len() && self.bit_index + 9 <= input_bits {
+            let pos = self.bit_index / 8;
+            let input = self.input();
+            let word = (input[pos] as u16) << 8 | input[pos + 1] as u16;
+            let code = word << (self.bit_index % 8);
+            if code & 0x8000 == 0 {
+                break;
+            }
+            // Report the states the fast path skips
+            #[cfg(feature = "trace")]
+            for (state, bit_index) in [
+                (HSDstate::HSDSTagBit, self.bit_index),
+                (HSDstate::HSDSYieldLiteral, self.bit_index + 1),
+            ] {
+                trace!(
+                    self.opts,
+                    DecodeEvent::State {
+                        name: state.name(),
+                        bit_index,
+                    }
+                );
+            }
+            trace!(
+                self.opts,
+                DecodeEvent::Literal {
+                    pos: self.head_index,
+                    byte: (code >> 7) as u8,
+                }
+            );
+            self.output[self.head_index] = (code >> 7) as u8;
+            self.head_index += 1;
+            self.bit_index += 9;
+            self.stats.literals += 1;
+        }
+    }
+
+    fn st_backref_index_msb(&mut self) -> HSDstate
+    where
+        C: Sizes,
+    {
+        let bit_ct = self.cfg.window_sz2() - 8;
         self.output_index = match self.get_bits(bit_ct) {
-            Some(idx) => idx << 8,
+            Some(idx) => (idx as usize) << 8,
             None => {
                 return HSDstate::HSDSNeedMoreData;
             }
@@ -147,36 +931,45 @@ impl<'a, 'b> HeatshrinkDecoder<'a, 'b> {
         HSDstate::HSDSBackrefIndexLsb
     }
 
-    fn st_backref_index_lsb(&mut self) -> HSDstate {
-        let bit_ct = self.cfg.window_sz2.min(8);
+    fn st_backref_index_lsb(&mut self) -> HSDstate
+    where
+        C: Sizes,
+    {
+        let bit_ct = self.cfg.window_sz2().min(8);
         self.output_index = match self.get_bits(bit_ct) {
-            Some(idx) => self.output_index | idx,
+            Some(idx) => self.output_index | idx as usize,
             None => {
                 return HSDstate::HSDSNeedMoreData;
             }
         };
         self.output_index += 1;
         self.output_count = 0;
-        if self.cfg.lookahead_sz2 > 8 {
+        if self.cfg.lookahead_sz2() > 8 {
             HSDstate::HSDSBackrefCountMsb
         } else {
             HSDstate::HSDSBackrefCountLsb
         }
     }
 
-    fn st_backref_count_msb(&mut self) -> HSDstate {
-        let bit_ct = self.cfg.lookahead_sz2 - 8;
+    fn st_backref_count_msb(&mut self) -> HSDstate
+    where
+        C: Sizes,
+    {
+        let bit_ct = self.cfg.lookahead_sz2() - 8;
         self.output_count = match self.get_bits(bit_ct) {
             Some(idx) => idx << 8,
             None => {
                 return HSDstate::HSDSNeedMoreData;
             }
         };
-        HSDstate::HSDSBackrefIndexLsb
+        HSDstate::HSDSBackrefCountLsb
     }
 
-    fn st_backref_count_lsb(&mut self) -> HSDstate {
-        let bit_ct = self.cfg.lookahead_sz2.min(8);
+    fn st_backref_count_lsb(&mut self) -> HSDstate
+    where
+        C: Sizes,
+    {
+        let bit_ct = self.cfg.lookahead_sz2().min(8);
         self.output_count = match self.get_bits(bit_ct) {
             Some(idx) => self.output_count | idx,
             None => {
@@ -184,34 +977,86 @@ impl<'a, 'b> HeatshrinkDecoder<'a, 'b> {
             }
         };
         self.output_count += 1;
+        trace!(
+            self.opts,
+            DecodeEvent::Backref {
+                pos: self.head_index,
+                distance: self.output_index,
+                count: self.output_count as usize,
+            }
+        );
+        // The count field holds count - 1 in lookahead_sz2 bits
+        debug_assert!(self.output_count as u32 <= 1 << self.cfg.lookahead_sz2() as u32);
         HSDstate::HSDSYieldBackref
     }
 
     fn st_yield_backref(&mut self) -> HSDstate {
-        /*
-        println!(
-            "Backref: idx:{}  count:{} head_idx:{}",
-            self.output_index, self.output_count, self.head_index
-        );
-        */
         let mut count = self.output_count as usize;
         if self.head_index + count > self.output.len() {
             return HSDstate::OutputFull;
         }
-        while self.output_index as usize > self.head_index && count > 0 {
-            // C encoder refs an empty window filled with 0 bytes
-            // Allow for this in decoding to maintain compatibility
-            self.output[self.head_index] = 0;
-            self.head_index += 1;
-            count -= 1;
+        self.stats.backrefs += 1;
+        self.stats.backref_bytes += count;
+        let dist = self.output_index;
+        let before_start = dist > self.head_index + self.dict.len();
+        if self.opts.mismatch_check && self.mismatch.backref(self.head_index, before_start) {
+            return HSDstate::ConfigMismatch;
         }
-        if count > 0 {
-            let start_in = self.head_index - self.output_index as usize;
-            for i in 0..count {
-                self.output[self.head_index] = self.output[start_in + i];
-                self.head_index += 1;
+        if dist > self.head_index {
+            let before = dist - self.head_index;
+            if before > self.dict.len() && !self.opts.zero_window {
+                return HSDstate::IllegalBackref;
+            }
+            // Bytes before the output come from the dictionary, and before
+            // that from an empty window filled with 0 bytes, which the
+            // C encoder refs. Allow for this to maintain compatibility
+            let n = count.min(before);
+            for i in 0..n {
+                let back = before - i;
+                self.output[self.head_index + i] = if back <= self.dict.len() {
+                    self.dict[self.dict.len() - back]
+                } else {
+                    0
+                };
             }
+            self.head_index += n;
+            count -= n;
+        }
+        // Only bytes already written are read, as the source is
+        // always `dist` bytes behind the byte being written
+        for _ in 0..count {
+            self.output[self.head_index] = self.output[self.head_index - dist];
+            self.head_index += 1;
         }
         HSDstate::HSDSTagBit
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::HeatshrinkDecoder;
+    use crate::Config;
+
+    #[test]
+    fn get_bits_at_end() {
+        let input = [0xa5, 0x3c, 0xf0];
+        let all = u32::from_be_bytes([0, 0xa5, 0x3c, 0xf0]);
+        let cfg = Config::default();
+        let mut output = [0; 4];
+        let mut decoder = HeatshrinkDecoder::new(&input, &mut output, &cfg);
+        for start in 0..=24 {
+            for count in 1..=16u8 {
+                decoder.bit_index = start;
+                let end = start + count as usize;
+                if end > 24 {
+                    assert_eq!(decoder.get_bits(count), None);
+                    assert_eq!(decoder.bit_index, start);
+                } else {
+                    let expected = (all >> (24 - end)) & ((1 << count) - 1);
+                    assert_eq!(decoder.get_bits(count), Some(expected as u16));
+                    assert_eq!(decoder.bit_index, end);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use super::{Config, DecodeError, EncodeError, StreamDecoder, StreamEncoder};
+use embedded_io::{ErrorKind, ErrorType, Read, Write};
+
+/// Errors returned by the `embedded-io` adapters
+#[derive(Debug)]
+pub enum EmbeddedError<E> {
+    /// The inner reader or writer failed
+    Io(E),
+    /// The compressed data read was corrupt
+    Decode(DecodeError),
+    /// The encoder failed, see `StreamEncoder::error()`
+    Encode(EncodeError),
+}
+
+impl<E: embedded_io::Error> embedded_io::Error for EmbeddedError<E> {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            EmbeddedError::Io(err) => err.kind(),
+            EmbeddedError::Decode(DecodeError::OutputFull { .. }) => ErrorKind::WriteZero,
+            EmbeddedError::Decode(DecodeError::InvalidConfig) => ErrorKind::InvalidInput,
+            EmbeddedError::Decode(_) => ErrorKind::InvalidData,
+            EmbeddedError::Encode(_) => ErrorKind::Other,
+        }
+    }
+}
+
+/// Adapter that decompresses data read from an inner `embedded_io::Read`
+/// on the fly. All storage is supplied by the caller: `window` must be
+/// at least `1 << window_sz2` bytes, and `buffer` holds compressed data
+/// read ahead from the inner reader
+pub struct EmbeddedReader<R, W, I> {
+    inner: R,
+    decoder: StreamDecoder<W>,
+    buf: I,
+    pos: usize,
+    len: usize,
+    eof: bool,
+}
+
+impl<R: Read, W: AsMut<[u8]>, I: AsMut<[u8]>> EmbeddedReader<R, W, I> {
+    /// Creates a new reader decompressing the data from `inner`
+    ///
+    /// # Panics
+    ///
+    /// Panics if `window` is shorter than `1 << window_sz2` bytes, or `buffer` is empty
+    pub fn new(inner: R, window: W, mut buffer: I, cfg: &Config) -> Self {
+        assert!(!buffer.as_mut().is_empty(), "Input buffer is empty");
+        EmbeddedReader {
+            inner,
+            decoder: StreamDecoder::new(window, cfg),
+            buf: buffer,
+            pos: 0,
+            len: 0,
+            eof: false,
+        }
+    }
+
+    /// Consumes the adapter, returning the inner reader.
+    /// Compressed data that was read but not yet decoded is lost
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    fn fill_buf(&mut self) -> Result<(), EmbeddedError<R::Error>> {
+        loop {
+            match self.inner.read(self.buf.as_mut()) {
+                Ok(n) => {
+                    self.pos = 0;
+                    self.len = n;
+                    self.eof = n == 0;
+                    return Ok(());
+                }
+                Err(e) if embedded_io::Error::kind(&e) == ErrorKind::Interrupted => {}
+                Err(e) => return Err(EmbeddedError::Io(e)),
+            }
+        }
+    }
+}
+
+impl<R: Read, W, I> ErrorType for EmbeddedReader<R, W, I> {
+    type Error = EmbeddedError<R::Error>;
+}
+
+impl<R: Read, W: AsMut<[u8]>, I: AsMut<[u8]>> Read for EmbeddedReader<R, W, I> {
+    fn read(&mut self, out: &mut [u8]) -> Result<usize, Self::Error> {
+        if out.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let n = self.decoder.poll(out).map_err(EmbeddedError::Decode)?;
+            if n > 0 {
+                return Ok(n);
+            }
+            if self.pos == self.len {
+                if self.eof {
+                    return Ok(0);
+                }
+                self.fill_buf()?;
+            }
+            self.pos += self.decoder.sink(&self.buf.as_mut()[self.pos..self.len]);
+        }
+    }
+}
+
+/// Adapter that compresses data written to it on the fly, passing the
+/// compressed data on to an inner `embedded_io::Write`. All storage is
+/// supplied by the caller: `window` is used as described for `StreamEncoder`,
+/// and `buffer` collects compressed data before it is written.
+///
+/// The stream must be terminated by calling `finish()`
+pub struct EmbeddedWriter<W, B, O> {
+    inner: W,
+    encoder: StreamEncoder<B>,
+    buf: O,
+}
+
+impl<W: Write, B: AsMut<[u8]>, O: AsMut<[u8]>> EmbeddedWriter<W, B, O> {
+    /// Creates a new writer compressing data into `inner`
+    ///
+    /// # Panics
+    ///
+    /// Panics if `window` is too small for `StreamEncoder`, or `buffer` is empty
+    pub fn new(inner: W, window: B, mut buffer: O, cfg: &Config) -> Self {
+        assert!(!buffer.as_mut().is_empty(), "Output buffer is empty");
+        EmbeddedWriter {
+            inner,
+            encoder: StreamEncoder::new(window, cfg),
+            buf: buffer,
+        }
+    }
+
+    /// Compresses all remaining data, writes the final padded byte,
+    /// and returns the inner writer
+    pub fn finish(mut self) -> Result<W, EmbeddedError<W::Error>> {
+        // Drain before checking, so that a failed encoder is reported
+        loop {
+            self.drain()?;
+            if self.encoder.finish() {
+                break;
+            }
+        }
+        self.inner.flush().map_err(EmbeddedError::Io)?;
+        Ok(self.inner)
+    }
+
+    // Passes all compressed data the encoder is able to produce to the inner writer
+    fn drain(&mut self) -> Result<(), EmbeddedError<W::Error>> {
+        loop {
+            let n = self.encoder.poll(self.buf.as_mut());
+            if let Some(err) = self.encoder.error() {
+                return Err(EmbeddedError::Encode(err));
+            }
+            if n == 0 {
+                return Ok(());
+            }
+            self.inner
+                .write_all(&self.buf.as_mut()[..n])
+                .map_err(EmbeddedError::Io)?;
+        }
+    }
+}
+
+impl<W: Write, B, O> ErrorType for EmbeddedWriter<W, B, O> {
+    type Error = EmbeddedError<W::Error>;
+}
+
+impl<W: Write, B: AsMut<[u8]>, O: AsMut<[u8]>> Write for EmbeddedWriter<W, B, O> {
+    fn write(&mut self, data: &[u8]) -> Result<usize, Self::Error> {
+        if data.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let n = self.encoder.sink(data);
+            self.drain()?;
+            if n > 0 {
+                return Ok(n);
+            }
+        }
+    }
+
+    /// Writes all data written so far to the inner writer, using
+    /// `StreamEncoder::partial_flush()`, except for up to 7 bits of the
+    /// last symbol, since padding the stream here would corrupt it
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.encoder.partial_flush();
+        self.drain()?;
+        self.inner.flush().map_err(EmbeddedError::Io)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{EmbeddedError, EmbeddedReader, EmbeddedWriter};
+    use crate::{encode, Config, DecodeError, DecodeOptions, StreamDecoder};
+    use embedded_io::{Error, ErrorKind, Read, Write};
+
+    fn sample() -> [u8; 3000] {
+        let mut src = [0; 3000];
+        for (i, b) in src.iter_mut().enumerate() {
+            *b = (i * i % 251 % 13) as u8;
+        }
+        src
+    }
+
+    #[test]
+    fn round_trip() {
+        let src = sample();
+        let cfg = Config::new(8, 4).unwrap();
+        let mut compressed = [0; 4000];
+        let mut window = [0; 1024];
+        let mut buffer = [0; 16];
+        let mut writer =
+            EmbeddedWriter::new(&mut compressed[..], &mut window[..], &mut buffer[..], &cfg);
+        for part in src.chunks(100) {
+            writer.write_all(part).unwrap();
+        }
+        let remaining = writer.finish().unwrap().len();
+        let len = compressed.len() - remaining;
+        let mut expected = [0; 4000];
+        assert_eq!(
+            compressed[..len],
+            *encode(&src, &mut expected, &cfg).unwrap()
+        );
+
+        let mut window = [0; 256];
+        let mut buffer = [0; 7];
+        let mut reader =
+            EmbeddedReader::new(&compressed[..len], &mut window[..], &mut buffer[..], &cfg);
+        let mut decoded = [0; 3000];
+        reader.read_exact(&mut decoded).unwrap();
+        assert_eq!(decoded, src);
+        assert_eq!(reader.read(&mut decoded).unwrap(), 0);
+    }
+
+    #[test]
+    fn error_kinds() {
+        // A backref with distance 2 when only one byte has been decoded
+        let src = [0xb0, 0x80, 0xbc];
+        let opts = DecodeOptions::new().with_zero_window(false);
+        let cfg = Config::new(8, 4).unwrap();
+        let mut decoder = StreamDecoder::with_options([0; 256], &cfg, &opts);
+        decoder.sink(&src);
+        let err = decoder.poll(&mut [0; 20]).unwrap_err();
+        let illegal = DecodeError::IllegalBackref {
+            input_bit: 9,
+            output_pos: 1,
+        };
+        assert_eq!(err, illegal);
+        let err: EmbeddedError<embedded_io::SliceWriteError> = EmbeddedError::Decode(err);
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        let err: EmbeddedError<embedded_io::SliceWriteError> =
+            EmbeddedError::Io(embedded_io::SliceWriteError::Full);
+        assert_eq!(err.kind(), ErrorKind::WriteZero);
+    }
+}
@@ -1,136 +1,1068 @@
-use super::Config;
+use super::{BufferFull, ByteSink, Config, SinkError, Sizes};
 
-pub struct HeatshrinkEncoder<'a, 'b> {
-    cfg: Config,
-    bit_index: usize,
+/// One-shot encoder state, for callers that want to hold on to the encoder,
+/// like when embedding the codec in a custom protocol. `encode()` and its
+/// siblings are the simpler interface to the same encoder
+pub struct HeatshrinkEncoder<'a, 'b, C = Config> {
+    cfg: C,
+    opts: EncodeOptions,
+    start: usize,     // Input position where encoding starts, preceded by history
+    bounded: bool,    // Stop at the first symbol not fitting the output
+    bit_index: usize, // Number of bytes written
     bit_buf: u32,
     num_bits: u8,
 
+    dict: &'a [u8],
     input: &'a [u8],
     output: &'b mut [u8],
+    stats: EncodeStats,
 }
 
 /// Errors that may be encountered when compressing data
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum EncodeError {
     /// The output buffer was not large enough to hold the compressed data
     OutputFull,
+    /// The configuration is out of range, see `Config::new()`
+    InvalidConfig,
+    /// The encoder produced a field that does not fit its width. This
+    /// points to a bug in the encoder, caught by an assertion in debug
+    /// builds, and reported instead of panicking in release builds
+    Internal,
+}
+
+impl core::fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let msg = match self {
+            EncodeError::OutputFull => "Output is full",
+            EncodeError::InvalidConfig => "Invalid configuration",
+            EncodeError::Internal => "Internal encoder error",
+        };
+        f.write_str(msg)
+    }
+}
+
+impl core::error::Error for EncodeError {}
+
+/// Statistics on how the input was compressed, as returned by `encode_with_stats()`
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct EncodeStats {
+    /// Number of bytes emitted as literals
+    pub literals: usize,
+    /// Number of back-references emitted
+    pub backrefs: usize,
+    /// Number of uncompressed bytes
+    pub input_len: usize,
+    /// Number of compressed bytes
+    pub output_len: usize,
+}
+
+/// Tuning options for the encoder. These only affect which matches are
+/// selected, streams are decodable with the matching `Config` regardless
+#[derive(Debug, Copy, Clone)]
+pub struct EncodeOptions {
+    pub(crate) compat: bool,
+    pub(crate) lazy: bool,
+    pub(crate) farthest: bool,
+    pub(crate) max_chain_len: usize,
+    pub(crate) min_match_len: usize,
+    pub(crate) index_threshold: usize,
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        EncodeOptions {
+            compat: false,
+            lazy: false,
+            farthest: false,
+            max_chain_len: usize::MAX,
+            min_match_len: 0,
+            index_threshold: 256,
+        }
+    }
+}
+
+impl EncodeOptions {
+    /// Creates the default options
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Selects matches exactly like the reference C encoder, so that the
+    /// output is byte identical to it. The C encoder starts out with a zero
+    /// filled window, which back-references may point into
+    pub fn with_compat(mut self, compat: bool) -> Self {
+        self.compat = compat;
+        self
+    }
+
+    /// Enables one step lazy matching: a match is deferred in favour of a
+    /// literal when the match starting at the next byte is longer. This
+    /// usually improves the compression ratio at the cost of extra searching.
+    /// The stream is still decodable by any standard decoder, but it is no
+    /// longer byte identical to the C encoder output in compat mode
+    pub fn with_lazy(mut self, lazy: bool) -> Self {
+        self.lazy = lazy;
+        self
+    }
+
+    /// Resolves ties between equally long matches in favour of the farthest
+    /// one, rather than the nearest like the C encoder does, which is the
+    /// default. Distances take a fixed number of bits, so the size of the
+    /// output stays the same, but it is no longer byte identical to the C
+    /// encoder output. Searching is slower, as it can not stop at the first
+    /// match of the maximum length
+    pub fn with_farthest(mut self, farthest: bool) -> Self {
+        self.farthest = farthest;
+        self
+    }
+
+    /// Bounds how many candidate positions are examined when searching for
+    /// a match, nearest first. Only positions starting with the same byte as
+    /// the data to encode are counted. A low limit speeds up compression of
+    /// large inputs at the cost of the compression ratio. Unlimited by default
+    pub fn with_max_chain_len(mut self, max_chain_len: usize) -> Self {
+        self.max_chain_len = max_chain_len;
+        self
+    }
+
+    /// Only emits back-references of at least `min_match_len` bytes, on top
+    /// of the break-even length below which a back-reference is larger than
+    /// the literals it replaces. Raising it lowers the compression ratio, as
+    /// short matches turn into literals, most noticeably on data with few long
+    /// repetitions. It does not reduce the search effort, which
+    /// `with_max_chain_len()` does. The default of 0 keeps the break-even
+    pub fn with_min_match_len(mut self, min_match_len: usize) -> Self {
+        self.min_match_len = min_match_len;
+        self
+    }
+
+    /// Sets the input size from which the one-shot encoder allocates a hash
+    /// chain index, like `encode_with_scratch()` uses, instead of scanning
+    /// the whole window for matches. The output is the same either way.
+    /// Below about 64 bytes the scan is faster. From 256 bytes, the default,
+    /// the index is faster on most data, up to 10 times for a large window,
+    /// while data made of short repeated records gains little. The index
+    /// takes `2 << window_sz2` bytes plus 1 kB, and needs the `alloc`
+    /// feature, without which the whole window is always scanned.
+    /// `usize::MAX` disables the index
+    pub fn with_index_threshold(mut self, index_threshold: usize) -> Self {
+        self.index_threshold = index_threshold;
+        self
+    }
 }
 
 /// Basic compression call. Source and destination must reside in memory,
 /// and destination must be large enough to hold the compressed data,
-/// or an error will be returned
+/// or an error will be returned. Empty input compresses to empty output,
+/// which needs no room in the destination
 pub fn encode<'a>(
     input: &[u8],
     output: &'a mut [u8],
     cfg: &Config,
 ) -> Result<&'a [u8], EncodeError> {
-    let encoder = HeatshrinkEncoder::new(input, output, cfg);
-    encoder.encode()
+    encode_with_options(input, output, cfg, &Default::default())
+}
+
+/// Compression call like `encode()`, returning the number of bytes
+/// written to the start of `output` rather than borrowing them, for callers
+/// that own the buffer anyway, like behind a C interface
+pub fn encode_len(input: &[u8], output: &mut [u8], cfg: &Config) -> Result<usize, EncodeError> {
+    encode(input, output, cfg).map(|encoded| encoded.len())
+}
+
+/// Compression call like `encode()`, using the given encoder options
+pub fn encode_with_options<'a>(
+    input: &[u8],
+    output: &'a mut [u8],
+    cfg: &Config,
+    opts: &EncodeOptions,
+) -> Result<&'a [u8], EncodeError> {
+    encode_sized(input, output, *cfg, opts)
+}
+
+// Compresses like `encode_with_options()`, for sizes that may be constants
+pub(crate) fn encode_sized<'a, C: Sizes>(
+    input: &[u8],
+    output: &'a mut [u8],
+    cfg: C,
+    opts: &EncodeOptions,
+) -> Result<&'a [u8], EncodeError> {
+    let mut encoder = HeatshrinkEncoder::with_sizes(input, output, cfg, opts);
+    let len = encoder.run()?.output_len;
+    Ok(&encoder.output[..len])
+}
+
+/// Compression call like `encode()`, additionally returning statistics on
+/// how effective the compression was. This helps choosing `window_sz2` and
+/// `lookahead_sz2` for a given kind of data
+pub fn encode_with_stats<'a>(
+    input: &[u8],
+    output: &'a mut [u8],
+    cfg: &Config,
+) -> Result<(&'a [u8], EncodeStats), EncodeError> {
+    let mut encoder = HeatshrinkEncoder::new(input, output, cfg);
+    let stats = encoder.run()?;
+    Ok((&encoder.output[..stats.output_len], stats))
+}
+
+/// Compresses `input[history_len..]` as a continuation frame, with
+/// `input[..history_len]` serving as history that back-references may point
+/// into. Such frames are decoded with `decode_with_history()`, given the
+/// same history
+pub fn encode_with_history<'a>(
+    input: &[u8],
+    output: &'a mut [u8],
+    history_len: usize,
+    cfg: &Config,
+) -> Result<&'a [u8], EncodeError> {
+    let mut encoder = HeatshrinkEncoder::new(input, output, cfg);
+    encoder.start = history_len;
+    let len = encoder.run()?.output_len;
+    Ok(&encoder.output[..len])
+}
+
+/// Compression call returning the compressed data in a `heapless::Vec`
+/// holding up to `N` bytes, so that no allocator is needed. Output that
+/// does not fit results in `EncodeError::OutputFull`
+#[cfg(feature = "heapless")]
+pub fn encode_heapless<const N: usize>(
+    input: &[u8],
+    cfg: &Config,
+) -> Result<heapless::Vec<u8, N>, EncodeError> {
+    let mut output = heapless::Vec::new();
+    // Can not fail, as the length is the capacity
+    let _ = output.resize(N, 0);
+    let len = encode(input, &mut output, cfg)?.len();
+    output.truncate(len);
+    Ok(output)
+}
+
+/// Compression call like `encode()`, with the window preset to `dict`, so
+/// that back-references may point into it from the start. This improves the
+/// compression of short messages that resemble the dictionary. The
+/// dictionary is not part of the output, and the data must be decoded with
+/// `decode_with_dict()` given the same dictionary. Only its last
+/// `1 << window_sz2` bytes are ever referenced
+pub fn encode_with_dict<'a>(
+    input: &[u8],
+    output: &'a mut [u8],
+    dict: &[u8],
+    cfg: &Config,
+) -> Result<&'a [u8], EncodeError> {
+    let mut encoder = HeatshrinkEncoder::new(input, output, cfg);
+    encoder.dict = dict;
+    let len = encoder.run()?.output_len;
+    Ok(&encoder.output[..len])
+}
+
+/// Compression call compressing as much of `input` as fits in `output`,
+/// rather than failing with `EncodeError::OutputFull`, like for filling
+/// packets of a fixed size. Returns the compressed data, which decodes to
+/// `input[..consumed]` on its own, along with `consumed`, the number of
+/// input bytes it holds. The rest of the input can then be compressed
+/// into the next packet the same way
+pub fn encode_bounded<'a>(
+    input: &[u8],
+    output: &'a mut [u8],
+    cfg: &Config,
+) -> Result<(&'a [u8], usize), EncodeError> {
+    let mut encoder = HeatshrinkEncoder::new(input, output, cfg);
+    encoder.bounded = true;
+    let stats = encoder.run()?;
+    Ok((&encoder.output[..stats.output_len], stats.input_len))
+}
+
+/// Compression call for text, using `Config::text()`. Decompress the
+/// result with `decode()` given the same configuration
+pub fn encode_str<'a>(input: &str, output: &'a mut [u8]) -> Result<&'a [u8], EncodeError> {
+    encode(input.as_bytes(), output, &Config::text())
+}
+
+/// Compression call like `encode()`, finding matches through a hash chain
+/// kept in `scratch` instead of scanning the whole window. This is much
+/// faster for large windows, and produces exactly the same output. The
+/// scratch memory must hold at least `(1 << window_sz2) + 512` entries, so
+/// 2560 entries for a window of 11, and it panics when it is shorter. Its
+/// content on entry does not matter
+pub fn encode_with_scratch<'a>(
+    input: &[u8],
+    output: &'a mut [u8],
+    scratch: &mut [u16],
+    cfg: &Config,
+) -> Result<&'a [u8], EncodeError> {
+    if cfg.validate().is_err() {
+        return Err(EncodeError::InvalidConfig);
+    }
+    let needed = (1 << cfg.window_sz2) + 512;
+    assert!(scratch.len() >= needed, "scratch needs {} entries", needed);
+    let mut encoder = HeatshrinkEncoder::new(input, output, cfg);
+    let index = ChainIndex::new(scratch, cfg);
+    let len = encoder.run_on(input, Some(index))?.output_len;
+    Ok(&encoder.output[..len])
+}
+
+/// Compression call choosing the literals and back-references that give
+/// the smallest output, rather than greedily taking the longest match at
+/// each position like `encode()`. This dynamic programming over the whole
+/// input takes 12 bytes of memory per input byte on 64 bit targets, and 3 -
+/// 10 times the time of `encode()`, as matches are searched at every
+/// position, so it suits offline compression of data decompressed many
+/// times. The result is a standard stream. On text with the default
+/// configuration the output shrinks by about 4%, or 3% compared to lazy
+/// matching, which comes close with a window of 8 or a longer lookahead
+#[cfg(feature = "alloc")]
+pub fn encode_optimal<'a>(
+    input: &[u8],
+    output: &'a mut [u8],
+    cfg: &Config,
+) -> Result<&'a [u8], EncodeError> {
+    if cfg.validate().is_err() {
+        return Err(EncodeError::InvalidConfig);
+    }
+    let mut scratch = alloc::vec![0; (1 << cfg.window_sz2) + 512];
+    let mut index = ChainIndex::new(&mut scratch, cfg);
+    // Distance minus one and length of the longest match at each position
+    let mut matches = alloc::vec![(0u16, 0u16); input.len()];
+    for (pos, slot) in matches.iter_mut().enumerate() {
+        let (dist, len) = search(input, &[], Some(&mut index), pos, cfg, &Default::default());
+        if len > 0 {
+            *slot = ((dist - 1) as u16, len as u16);
+        }
+    }
+    // Fewest bits encoding the input from each position on, which never
+    // grow with the position, as dropping the first byte of a parse makes
+    // it no longer. Of the backrefs at a position the longest one is thus
+    // the best, and only it needs comparing against a literal
+    let backref_bits = 1 + cfg.window_sz2 as usize + cfg.lookahead_sz2 as usize;
+    let mut cost = alloc::vec![0; input.len() + 1];
+    for pos in (0..input.len()).rev() {
+        let len = matches[pos].1 as usize;
+        let literal = 9 + cost[pos + 1];
+        if len > 0 && backref_bits + cost[pos + len] <= literal {
+            cost[pos] = backref_bits + cost[pos + len];
+        } else {
+            cost[pos] = literal;
+            matches[pos].1 = 0;
+        }
+    }
+    let mut encoder = HeatshrinkEncoder::new(input, output, cfg);
+    let output = core::mem::take(&mut encoder.output);
+    let res = encoder.emit_parse(&matches, &mut &mut output[..]);
+    let len = res.map_err(slice_error)?;
+    Ok(&output[..len])
+}
+
+// Random access to the data being compressed, which may be split
+// into several segments like in `encode_vectored()`
+pub(crate) trait Input {
+    fn size(&self) -> usize;
+
+    fn at(&self, idx: usize) -> u8;
+
+    // Number of equal bytes at `idx1` and `idx2`, comparing up to `size` bytes
+    fn match_len(&self, idx1: usize, idx2: usize, size: usize) -> usize {
+        (0..size)
+            .position(|i| self.at(idx1 + i) != self.at(idx2 + i))
+            .unwrap_or(size)
+    }
+}
+
+impl Input for [u8] {
+    fn size(&self) -> usize {
+        self.len()
+    }
+
+    fn at(&self, idx: usize) -> u8 {
+        self[idx]
+    }
+
+    fn match_len(&self, idx1: usize, idx2: usize, size: usize) -> usize {
+        let a = &self[idx1..idx1 + size];
+        let b = &self[idx2..idx2 + size];
+        a.iter().zip(b).position(|(x, y)| x != y).unwrap_or(size)
+    }
+}
+
+// Segments forming the input of `encode_vectored()`
+struct Vectored<'a> {
+    segments: &'a [&'a [u8]],
+    size: usize,
+}
+
+impl Input for Vectored<'_> {
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn at(&self, mut idx: usize) -> u8 {
+        for segment in self.segments {
+            if idx < segment.len() {
+                return segment[idx];
+            }
+            idx -= segment.len();
+        }
+        panic!("Index beyond the input");
+    }
+}
+
+/// Compression call like `encode()` for input that is split into several
+/// segments, like a header and a payload, sparing the copy into one buffer.
+/// The segments are compressed as if they were concatenated, so the result
+/// is identical to `encode()` of the concatenation, and back-references span
+/// segment boundaries. Intended for a handful of segments, as finding the
+/// segment holding a byte takes time proportional to their number
+pub fn encode_vectored<'a>(
+    inputs: &[&[u8]],
+    output: &'a mut [u8],
+    cfg: &Config,
+) -> Result<&'a [u8], EncodeError> {
+    let input = Vectored {
+        segments: inputs,
+        size: inputs.iter().map(|segment| segment.len()).sum(),
+    };
+    let mut encoder = HeatshrinkEncoder::new(&[], output, cfg);
+    let len = encoder.run_on(&input, None)?.output_len;
+    Ok(&encoder.output[..len])
+}
+
+/// Returns the exact number of bytes `encode()` produces for `input`,
+/// without writing any output. This runs the complete match search, so it
+/// takes as long as compressing, but needs no output buffer. It helps
+/// deciding whether to store data compressed or raw
+pub fn encode_bound_exact(input: &[u8], cfg: &Config) -> Result<usize, EncodeError> {
+    if cfg.validate().is_err() {
+        return Err(EncodeError::InvalidConfig);
+    }
+    let (backrefs, matched) = scan(input, cfg, &Default::default());
+    let backref_bits = 1 + cfg.window_sz2 as usize + cfg.lookahead_sz2 as usize;
+    let bits = backrefs * backref_bits + (input.len() - matched) * 9;
+    Ok(bits.div_ceil(8))
+}
+
+// Runs the match search over `input` like the encoder, returning the number
+// of back-references and the number of bytes they cover
+pub(crate) fn scan(input: &[u8], cfg: &Config, opts: &EncodeOptions) -> (usize, usize) {
+    let mut backrefs = 0;
+    let mut matched = 0;
+    let mut pos = 0;
+    while pos < input.len() {
+        match find_backref(input, &[], None, pos, cfg, opts) {
+            Some((_, len)) => {
+                backrefs += 1;
+                matched += len as usize;
+                pos += len as usize;
+            }
+            None => pos += 1,
+        }
+    }
+    (backrefs, matched)
+}
+
+// Length of the match between the data at `idx1` and `idx2`. The match may
+// overlap the data at `idx2`, as the decoder copies one byte at a time,
+// so that runs are encoded as a back-reference at distance 1
+fn cmp<I: Input + ?Sized, C: Sizes>(input: &I, idx1: usize, idx2: usize, cfg: &C) -> u32 {
+    assert!(idx1 < idx2);
+    let size = 1 << cfg.lookahead_sz2() as usize;
+    let end = input.size().min(idx2 + size);
+    input.match_len(idx1, idx2, end - idx2) as u32
+}
+
+// Longest match possible at `head`, limited by the lookahead and the end of
+// the input
+fn max_match_len<I: Input + ?Sized, C: Sizes>(input: &I, head: usize, cfg: &C) -> u32 {
+    (input.size() - head).min(1 << cfg.lookahead_sz2() as usize) as u32
+}
+
+// Byte `back` positions before the start of the input, which lies in the
+// dictionary or the zero filled window preceding it
+fn prefix_byte(dict: &[u8], back: usize) -> u8 {
+    if back <= dict.len() {
+        dict[dict.len() - back]
+    } else {
+        0
+    }
+}
+
+// Match length against a window where `dist` exceeds the input seen so far,
+// so that the match starts in the dictionary or the zero filled window
+fn cmp_prefix<I: Input + ?Sized, C: Sizes>(
+    input: &I,
+    dict: &[u8],
+    dist: usize,
+    head: usize,
+    cfg: &C,
+) -> u32 {
+    let maxlen = input.size().min(head + (1 << cfg.lookahead_sz2() as usize)) - head;
+    let before = dist - head;
+    let mut len = 0;
+    while len < maxlen {
+        let byte = if len < before {
+            prefix_byte(dict, before - len)
+        } else {
+            input.at(len - before)
+        };
+        if byte != input.at(head + len) {
+            break;
+        }
+        len += 1;
+    }
+    len as u32
+}
+
+// Hash chain index over the window, linking each position to the previous
+// position holding the same byte, so that the search only visits candidates
+// that match at least one byte. It lives in caller provided scratch memory
+pub(crate) struct ChainIndex<'s> {
+    // Distance minus one to the previous position with the same byte, for the
+    // last window of positions. 0 also serves as the end of the chain, which
+    // is recognized by the byte at distance 1 not matching
+    chain: &'s mut [u16],
+    // Last position plus one holding each byte value, split in two halves
+    last: &'s mut [u16],
+    indexed: usize, // Positions before this are in the index
+}
+
+impl<'s> ChainIndex<'s> {
+    fn new<C: Sizes>(scratch: &'s mut [u16], cfg: &C) -> Self {
+        let (chain, last) = scratch.split_at_mut(1 << cfg.window_sz2());
+        let last = &mut last[..512];
+        last.fill(0);
+        ChainIndex {
+            chain,
+            last,
+            indexed: 0,
+        }
+    }
+
+    fn last(&self, byte: u8) -> Option<usize> {
+        let i = byte as usize * 2;
+        let pos = (self.last[i] as usize) << 16 | self.last[i + 1] as usize;
+        pos.checked_sub(1)
+    }
+
+    // Adds all positions before `end` to the index
+    fn update<I: Input + ?Sized>(&mut self, input: &I, end: usize) {
+        let wsize = self.chain.len();
+        while self.indexed < end {
+            let pos = self.indexed;
+            let byte = input.at(pos);
+            self.chain[pos % wsize] = match self.last(byte) {
+                Some(prev) if pos - prev <= wsize => (pos - prev - 1) as u16,
+                _ => 0,
+            };
+            let i = byte as usize * 2;
+            self.last[i] = ((pos + 1) >> 16) as u16;
+            self.last[i + 1] = (pos + 1) as u16;
+            self.indexed += 1;
+        }
+    }
+
+    // Distances of the positions in the window holding the byte at `head`,
+    // nearest first. All positions before `head` must be indexed
+    fn candidates<'i, I: Input + ?Sized>(
+        &'i self,
+        input: &'i I,
+        head: usize,
+    ) -> impl Iterator<Item = usize> + 'i {
+        let wsize = self.chain.len();
+        let first = input.at(head);
+        let mut next = self.last(first);
+        core::iter::from_fn(move || {
+            let pos = next?;
+            let dist = head - pos;
+            if dist > wsize {
+                return None;
+            }
+            let link = self.chain[pos % wsize] as usize + 1;
+            next = pos
+                .checked_sub(link)
+                .filter(|prev| input.at(*prev) == first);
+            Some(dist)
+        })
+    }
+}
+
+// Visits the candidates at distances `dists`, which all start with the byte
+// at `head`, updating `best`. Returns false once `chain` is exhausted
+fn visit<I: Input + ?Sized, C: Sizes>(
+    input: &I,
+    head: usize,
+    cfg: &C,
+    dists: impl Iterator<Item = usize>,
+    farthest: bool,
+    chain: &mut usize,
+    best: &mut (usize, u32),
+) -> bool {
+    let maxlen = max_match_len(input, head, cfg);
+    for dist in dists {
+        if *chain == 0 {
+            return false;
+        }
+        *chain -= 1;
+        let clen = cmp(input, head - dist, head, cfg);
+        if clen > best.1 || (farthest && clen == best.1) {
+            *best = (dist, clen);
+            // Nothing further away can do better
+            if clen == maxlen && !farthest {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+// Returns the distance and length of the longest match, preferring the
+// smallest distance among equally long matches, unless the options ask for
+// the largest. Candidates are visited
+// nearest first, and only those starting with the byte at `head` count
+// towards the `max_chain_len` limit, as the others cannot match at all.
+// The search ends early on a match of the longest possible length.
+// With an index the candidates are found through it, rather than by
+// scanning the window, which gives the same result
+fn search<I: Input + ?Sized, C: Sizes>(
+    input: &I,
+    dict: &[u8],
+    index: Option<&mut ChainIndex>,
+    head: usize,
+    cfg: &C,
+    opts: &EncodeOptions,
+) -> (usize, u32) {
+    let wsize = 1 << cfg.window_sz2();
+    let first = input.at(head);
+    let mut chain = opts.max_chain_len;
+    let mut best = (0, 0);
+    let more = match index {
+        Some(index) => {
+            index.update(input, head);
+            let dists = index.candidates(input, head);
+            visit(
+                input,
+                head,
+                cfg,
+                dists,
+                opts.farthest,
+                &mut chain,
+                &mut best,
+            )
+        }
+        None => {
+            let dists = (1..=head.min(wsize)).filter(|dist| input.at(head - dist) == first);
+            visit(
+                input,
+                head,
+                cfg,
+                dists,
+                opts.farthest,
+                &mut chain,
+                &mut best,
+            )
+        }
+    };
+    if !more {
+        return best;
+    }
+    // Matches into the zero filled window must start with a zero byte
+    let end = if opts.compat && first == 0 {
+        wsize
+    } else {
+        wsize.min(head + dict.len())
+    };
+    for dist in head + 1..=end {
+        if prefix_byte(dict, dist - head) != first {
+            continue;
+        }
+        if chain == 0 {
+            return best;
+        }
+        chain -= 1;
+        let clen = cmp_prefix(input, dict, dist, head, cfg);
+        if clen > best.1 || (opts.farthest && clen == best.1) {
+            best = (dist, clen);
+            if clen == max_match_len(input, head, cfg) && !opts.farthest {
+                break;
+            }
+        }
+    }
+    best
+}
+
+/// Returns the `bit_cnt` bits padding the final byte. These are zero bits
+/// like the C encoder uses, unless the configuration is so small that a
+/// backref fits in the padding, which would then decode as trailing backrefs.
+/// One bits are used instead, which decode as an incomplete literal
+pub(crate) fn padding<C: Sizes>(cfg: &C, bit_cnt: u8) -> u16 {
+    if 1 + cfg.window_sz2() + cfg.lookahead_sz2() <= bit_cnt {
+        (1 << bit_cnt) - 1
+    } else {
+        0
+    }
+}
+
+/// Finds the back-reference to emit at `pos`, returning the distance
+/// and length of the match, or None if a literal should be emitted.
+/// Only `dict` followed by `input[..pos]` is considered as history, and
+/// the match may not extend past the end of `input`
+pub(crate) fn find_backref<I: Input + ?Sized, C: Sizes>(
+    input: &I,
+    dict: &[u8],
+    mut index: Option<&mut ChainIndex>,
+    pos: usize,
+    cfg: &C,
+    opts: &EncodeOptions,
+) -> Option<(usize, u32)> {
+    // A backref is only emitted when its bits are fewer than those of the
+    // bytes it replaces, counted at 8 bits per byte like the C encoder does
+    let backref_bits = 1 + cfg.window_sz2() as u32 + cfg.lookahead_sz2() as u32;
+    let maxlen = 1 << cfg.lookahead_sz2() as u32;
+    // Within runs of a repeated byte, like the zero runs of sparse data, the
+    // nearest candidate already matches the maximum length. This is what
+    // the search would return, without scanning the whole window
+    let (dist, len) = if pos > 0
+        && opts.max_chain_len > 0
+        && !opts.farthest
+        && cmp(input, pos - 1, pos, cfg) == maxlen
+    {
+        (1, maxlen)
+    } else {
+        search(input, dict, index.as_deref_mut(), pos, cfg, opts)
+    };
+    if len * 8 <= backref_bits || (len as usize) < opts.min_match_len {
+        return None;
+    }
+    // No match is longer than the maximum length
+    if opts.lazy && len < maxlen && pos + 1 < input.size() {
+        let (_, next_len) = search(input, dict, index, pos + 1, cfg, opts);
+        if next_len > len {
+            return None;
+        }
+    }
+    Some((dist, len))
 }
 
 impl<'a, 'b> HeatshrinkEncoder<'a, 'b> {
-    fn new(input: &'a [u8], output: &'b mut [u8], cfg: &Config) -> Self {
-        let bit_index = 0;
-        let bit_buf = 0;
-        let num_bits = 0;
+    /// Creates an encoder compressing `input` into `output`
+    pub fn new(input: &'a [u8], output: &'b mut [u8], cfg: &Config) -> Self {
+        HeatshrinkEncoder::with_options(input, output, cfg, &Default::default())
+    }
+
+    /// Creates an encoder like `new()`, using the given encoder options
+    pub fn with_options(
+        input: &'a [u8],
+        output: &'b mut [u8],
+        cfg: &Config,
+        opts: &EncodeOptions,
+    ) -> Self {
+        HeatshrinkEncoder::with_sizes(input, output, *cfg, opts)
+    }
+
+    /// Compresses all of the input, returning the compressed data.
+    /// Calling it again compresses the input from scratch
+    pub fn encode(&mut self) -> Result<&[u8], EncodeError> {
+        let len = self.run()?.output_len;
+        Ok(&self.output[..len])
+    }
+}
+
+// The sizes are bound per method rather than on the impl, as the trait
+// is private to the crate
+impl<'a, 'b, C> HeatshrinkEncoder<'a, 'b, C> {
+    // Creates an encoder for sizes that need not come from a `Config`
+    fn with_sizes(input: &'a [u8], output: &'b mut [u8], cfg: C, opts: &EncodeOptions) -> Self {
         HeatshrinkEncoder {
-            cfg: *cfg,
-            bit_index,
-            bit_buf,
-            num_bits,
+            cfg,
+            opts: *opts,
+            start: 0,
+            bounded: false,
+            bit_index: 0,
+            bit_buf: 0,
+            num_bits: 0,
+            dict: &[],
             input,
             output,
+            stats: Default::default(),
         }
     }
 
-    fn cmp(&self, idx1: usize, idx2: usize) -> u32 {
-        assert!(idx1 < idx2);
-        let size = 1 << self.cfg.lookahead_sz2 as usize;
-        let end = self.input.len().min(idx2 + size);
-        let size = end - idx2;
-        let mut matched = 0;
-        let all_match = self.input[idx1..idx1 + size]
-            .iter()
-            .enumerate()
-            .zip(self.input[idx2..idx2 + size].iter())
-            .map(|((i, a), b)| {
-                if *a != *b {
-                    matched = i as u32;
-                    false
-                } else {
-                    true
-                }
-            })
-            .all(|bt| bt);
-        if all_match {
-            size as u32
-        } else {
-            matched
+    fn run(&mut self) -> Result<EncodeStats, EncodeError>
+    where
+        C: Sizes,
+    {
+        let output = core::mem::take(&mut self.output);
+        let limit = self.bounded.then_some(output.len());
+        let res = self.run_into(&mut &mut output[..], limit);
+        self.output = output;
+        res.map_err(slice_error)
+    }
+
+    fn run_on<I: Input + ?Sized>(
+        &mut self,
+        input: &I,
+        index: Option<ChainIndex>,
+    ) -> Result<EncodeStats, EncodeError>
+    where
+        C: Sizes,
+    {
+        let output = core::mem::take(&mut self.output);
+        let limit = self.bounded.then_some(output.len());
+        let res = self.run_to(input, index, &mut &mut output[..], limit);
+        self.output = output;
+        res.map_err(slice_error)
+    }
+
+    // Compresses the input into `sink`, stopping at the first symbol that
+    // does not fit in `limit` bytes, if given
+    pub(crate) fn run_into<S: ByteSink>(
+        &mut self,
+        sink: &mut S,
+        limit: Option<usize>,
+    ) -> Result<EncodeStats, SinkError<S::Error>>
+    where
+        C: Sizes,
+    {
+        let input = self.input;
+        #[cfg(feature = "alloc")]
+        if input.len() >= self.opts.index_threshold && self.cfg.config().validate().is_ok() {
+            let mut scratch = alloc::vec![0; (1 << self.cfg.window_sz2()) + 512];
+            let index = ChainIndex::new(&mut scratch, &self.cfg);
+            return self.run_to(input, Some(index), sink, limit);
         }
+        self.run_to(input, None, sink, limit)
     }
 
-    fn search(&self, head: usize) -> (usize, u32) {
-        let wsize = 1 << self.cfg.window_sz2;
-        let start = if wsize > head { 0 } else { head - wsize };
-        let mut best = (0, 0);
-        for pos in start..head {
-            let clen = self.cmp(pos, head);
-            if clen >= best.1 {
-                best = (pos, clen);
+    fn run_to<I: Input + ?Sized, S: ByteSink>(
+        &mut self,
+        input: &I,
+        mut index: Option<ChainIndex>,
+        sink: &mut S,
+        limit: Option<usize>,
+    ) -> Result<EncodeStats, SinkError<S::Error>>
+    where
+        C: Sizes,
+    {
+        // Configs made with new_unchecked() would produce undecodable streams
+        if self.cfg.config().validate().is_err() {
+            return Err(SinkError::Encode(EncodeError::InvalidConfig));
+        }
+        self.bit_index = 0;
+        self.bit_buf = 0;
+        self.num_bits = 0;
+        self.stats = Default::default();
+        let backref_bits = 1 + self.cfg.window_sz2() + self.cfg.lookahead_sz2();
+        let mut pos = self.start;
+        while pos < input.size() {
+            let index = index.as_mut();
+            let mut backref = find_backref(input, self.dict, index, pos, &self.cfg, &self.opts);
+            if let Some(limit) = limit {
+                // A literal may still fit in place of a longer backref
+                match backref {
+                    Some(_) if self.fits(limit, backref_bits) => {}
+                    _ if self.fits(limit, 9) => backref = None,
+                    _ => break,
+                }
+            }
+            if let Some((rel, len)) = backref {
+                self.emit_bits(sink, 0, 1)?;
+                self.emit_bits(sink, (rel - 1) as u16, self.cfg.window_sz2())?;
+                self.emit_bits(sink, (len - 1) as u16, self.cfg.lookahead_sz2())?;
+                pos += len as usize;
+                self.stats.backrefs += 1;
+            } else {
+                let code = input.at(pos) as u16 | 0x0100;
+                self.emit_bits(sink, code, 9)?;
+                pos += 1;
+                self.stats.literals += 1;
             }
         }
-        best
+
+        self.flush(sink)?;
+        self.stats.input_len = pos - self.start;
+        self.stats.output_len = self.bit_index;
+        Ok(self.stats)
     }
 
-    fn encode(mut self) -> Result<&'b [u8], EncodeError> {
-        let threshold = (1 + self.cfg.lookahead_sz2 + self.cfg.window_sz2) as u32 / 8;
+    // Emits the symbols chosen by `encode_optimal()`, the match at each
+    // position with a length of 0 standing for a literal, returning the
+    // number of bytes written
+    #[cfg(feature = "alloc")]
+    fn emit_parse<S: ByteSink>(
+        &mut self,
+        matches: &[(u16, u16)],
+        sink: &mut S,
+    ) -> Result<usize, SinkError<S::Error>>
+    where
+        C: Sizes,
+    {
         let mut pos = 0;
         while pos < self.input.len() {
-            let (spos, len) = self.search(pos);
-            if len > threshold {
-                self.emit_bits(0, 1)?;
-                let rel = pos - spos;
-                // println!("Ref: {} len {}", rel, len);
-                self.emit_bits((rel - 1) as u16, self.cfg.window_sz2)?;
-                self.emit_bits((len - 1) as u16, self.cfg.lookahead_sz2)?;
-                pos += len as usize;
-            } else {
-                let code = self.input[pos] as u16 | 0x0100;
-                self.emit_bits(code, 9)?;
-                pos += 1;
+            match matches[pos] {
+                (_, 0) => {
+                    self.emit_bits(sink, self.input[pos] as u16 | 0x0100, 9)?;
+                    pos += 1;
+                }
+                (dist, len) => {
+                    self.emit_bits(sink, 0, 1)?;
+                    self.emit_bits(sink, dist, self.cfg.window_sz2())?;
+                    self.emit_bits(sink, len - 1, self.cfg.lookahead_sz2())?;
+                    pos += len as usize;
+                }
             }
         }
+        self.flush(sink)?;
+        Ok(self.bit_index)
+    }
 
-        self.flush()?;
-        Ok(&self.output[..self.bit_index])
+    // Whether `bit_cnt` more bits fit in `limit` bytes, along with the padding
+    fn fits(&self, limit: usize, bit_cnt: u8) -> bool {
+        let bits = self.bit_index * 8 + (self.num_bits + bit_cnt) as usize;
+        bits.div_ceil(8) <= limit
     }
 
-    fn emit_bits(&mut self, val: u16, bit_cnt: u8) -> Result<(), EncodeError> {
-        assert!(val < (1 << bit_cnt as u16));
+    fn emit_bits<S: ByteSink>(
+        &mut self,
+        sink: &mut S,
+        val: u16,
+        bit_cnt: u8,
+    ) -> Result<(), SinkError<S::Error>> {
+        // An out of range value would corrupt the neighbouring fields
+        debug_assert!((val as u32) < (1 << bit_cnt as u32));
+        if val as u32 >= 1 << bit_cnt as u32 {
+            return Err(SinkError::Encode(EncodeError::Internal));
+        }
         self.bit_buf = (self.bit_buf << bit_cnt) | val as u32;
         self.num_bits += bit_cnt;
         while self.num_bits >= 8 {
-            if self.bit_index >= self.output.len() {
-                return Err(EncodeError::OutputFull);
-            }
-            self.output[self.bit_index] = (self.bit_buf >> (self.num_bits - 8)) as u8;
+            let byte = (self.bit_buf >> (self.num_bits - 8)) as u8;
+            sink.write_bytes(&[byte]).map_err(SinkError::Sink)?;
             self.bit_index += 1;
             self.num_bits -= 8;
         }
         Ok(())
     }
 
-    fn flush(&mut self) -> Result<(), EncodeError> {
+    fn flush<S: ByteSink>(&mut self, sink: &mut S) -> Result<(), SinkError<S::Error>>
+    where
+        C: Sizes,
+    {
         // There are maximum 7 unwritten bits in the bitbuffer
         if self.num_bits > 0 {
-            if self.output.len() <= self.bit_index {
-                return Err(EncodeError::OutputFull);
-            }
-            self.output[self.bit_index] = (self.bit_buf << (8 - self.num_bits)) as u8;
+            let pad_bits = 8 - self.num_bits;
+            let padding = padding(&self.cfg, pad_bits) as u32;
+            let byte = (self.bit_buf << pad_bits | padding) as u8;
+            sink.write_bytes(&[byte]).map_err(SinkError::Sink)?;
             self.bit_index += 1;
             self.num_bits = 0;
         }
         Ok(())
     }
 }
+
+// Errors of the output slice, which only fails when full
+fn slice_error(err: SinkError<BufferFull>) -> EncodeError {
+    match err {
+        SinkError::Encode(err) => err,
+        _ => EncodeError::OutputFull,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{find_backref, ChainIndex, EncodeOptions, HeatshrinkEncoder, Input, Vectored};
+    use crate::{decode, encode_with_options, Config};
+
+    #[test]
+    fn break_even() {
+        // A match of `len` bytes is emitted exactly when it takes fewer bits
+        // than 8 per byte, including configs whose backrefs are not a whole
+        // number of bytes
+        let opts = EncodeOptions::new();
+        for (window, lookahead) in [(4, 3), (8, 3), (12, 4), (13, 6), (16, 15)] {
+            let cfg = Config::new(window, lookahead).unwrap();
+            let backref_bits = 1 + window as usize + lookahead as usize;
+            for len in 1..=6 {
+                let mut input = [0; 13];
+                for (i, b) in input[..len].iter_mut().enumerate() {
+                    *b = b'a' + i as u8;
+                }
+                input[len] = b'-';
+                input.copy_within(..len, len + 1);
+                let input = &input[..2 * len + 1];
+                let found = find_backref(input, &[], None, len + 1, &cfg, &opts);
+                let expected = (len * 8 > backref_bits).then_some((len + 1, len as u32));
+                assert_eq!(found, expected, "{:?} {}", cfg, len);
+            }
+        }
+    }
+
+    #[test]
+    fn match_len() {
+        // The slice comparison agrees with the byte by byte one of the trait
+        let mut input = [0; 200];
+        for (i, b) in input.iter_mut().enumerate() {
+            *b = b"abcabd"[i * 7 % 13 % 6];
+        }
+        input[150..].fill(b'a');
+        let segments = [&input[..]];
+        let vectored = Vectored {
+            segments: &segments,
+            size: input.len(),
+        };
+        for idx1 in 0..input.len() {
+            for idx2 in idx1 + 1..input.len() {
+                let size = (input.len() - idx2).min(40);
+                let len = input[..].match_len(idx1, idx2, size);
+                assert_eq!(len, vectored.match_len(idx1, idx2, size));
+                assert!(len == size || input[idx1 + len] != input[idx2 + len]);
+            }
+        }
+        assert_eq!(input[..].match_len(0, 1, 0), 0);
+    }
+
+    #[test]
+    fn tie_break() {
+        let input = b"abcXabcYabc";
+        let cfg = Config::new(8, 4).unwrap();
+        let far = EncodeOptions::new().with_farthest(true);
+        let mut scratch = [0; 256 + 512];
+        for (opts, expected) in [(EncodeOptions::new(), (4, 3)), (far, (8, 3))] {
+            assert_eq!(
+                find_backref(&input[..], &[], None, 8, &cfg, &opts),
+                Some(expected)
+            );
+            let mut index = ChainIndex::new(&mut scratch, &cfg);
+            let found = find_backref(&input[..], &[], Some(&mut index), 8, &cfg, &opts);
+            assert_eq!(found, Some(expected));
+        }
+        // Runs match at every distance, and the farthest is taken
+        let found = find_backref(&[7; 40][..], &[], None, 30, &cfg, &far);
+        assert_eq!(found, Some((30, 10)));
+        let src: [u8; 300] = core::array::from_fn(|i| ((i % 13) ^ (i % 7)) as u8);
+        let mut dst1 = [0; 300];
+        let mut dst2 = [0; 300];
+        let encoded = encode_with_options(&src, &mut dst1, &cfg, &far).unwrap();
+        assert_eq!(decode(encoded, &mut dst2, &cfg).unwrap(), src);
+    }
+
+    #[test]
+    #[cfg(not(debug_assertions))]
+    fn emit_out_of_range() {
+        use crate::{EncodeError, SinkError};
+        let mut dst = [0; 4];
+        let mut sink = &mut dst[..];
+        let mut encoder = HeatshrinkEncoder::new(b"", &mut [], &Config::default());
+        assert_eq!(encoder.emit_bits(&mut sink, 0x0f, 4), Ok(()));
+        let res = encoder.emit_bits(&mut sink, 0x10, 4);
+        assert_eq!(res, Err(SinkError::Encode(EncodeError::Internal)));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic]
+    fn emit_out_of_range_asserts() {
+        let mut dst = [0; 4];
+        let mut sink = &mut dst[..];
+        let mut encoder = HeatshrinkEncoder::new(b"", &mut [], &Config::default());
+        let _ = encoder.emit_bits(&mut sink, 0x10, 4);
+    }
+}
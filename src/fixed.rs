@@ -0,0 +1,91 @@
+use super::decoder::decode_sized;
+use super::encoder::encode_sized;
+use super::{Config, DecodeError, EncodeError, Sizes};
+
+// Checks the sizes at compile time, failing the build for invalid ones
+const fn fixed_config(window_sz2: u8, lookahead_sz2: u8) -> Config {
+    match Config::new_unchecked(window_sz2, lookahead_sz2).validate() {
+        Ok(cfg) => cfg,
+        Err(_) => panic!("Invalid window or lookahead size"),
+    }
+}
+
+/// Encoder with the window and lookahead sizes fixed at compile time, for
+/// code that never changes its configuration. The encoder is compiled for
+/// the sizes, so that the bit widths of the back-references and the bounds
+/// of the match search are constants. Sizes that `Config::new()` would
+/// reject fail the build rather than returning an error
+///
+/// `FixedEncoder::<11, 4>::encode(input, output)` produces the same output
+/// as `encode()` with `Config::new(11, 4)`
+#[derive(Debug, Copy, Clone)]
+pub struct FixedEncoder<const WINDOW: u8, const LOOKAHEAD: u8>;
+
+impl<const WINDOW: u8, const LOOKAHEAD: u8> FixedEncoder<WINDOW, LOOKAHEAD> {
+    /// The configuration for the fixed sizes
+    pub const CONFIG: Config = fixed_config(WINDOW, LOOKAHEAD);
+
+    /// Compresses `input` like `encode()`
+    #[inline]
+    pub fn encode<'a>(input: &[u8], output: &'a mut [u8]) -> Result<&'a [u8], EncodeError> {
+        encode_sized(input, output, Self, &Default::default())
+    }
+}
+
+impl<const WINDOW: u8, const LOOKAHEAD: u8> Sizes for FixedEncoder<WINDOW, LOOKAHEAD> {
+    #[inline]
+    fn config(&self) -> Config {
+        Self::CONFIG
+    }
+}
+
+/// Decoder with the window and lookahead sizes fixed at compile time, the
+/// counterpart of `FixedEncoder`, likewise compiled for the sizes
+#[derive(Debug, Copy, Clone)]
+pub struct FixedDecoder<const WINDOW: u8, const LOOKAHEAD: u8>;
+
+impl<const WINDOW: u8, const LOOKAHEAD: u8> FixedDecoder<WINDOW, LOOKAHEAD> {
+    /// The configuration for the fixed sizes
+    pub const CONFIG: Config = fixed_config(WINDOW, LOOKAHEAD);
+
+    /// Size of the window a `StreamDecoder` for these sizes needs
+    pub const WINDOW_SIZE: usize = 1 << WINDOW;
+
+    /// Decompresses `input` like `decode()`
+    #[inline]
+    pub fn decode<'a>(input: &[u8], output: &'a mut [u8]) -> Result<&'a [u8], DecodeError> {
+        decode_sized(input, output, Self, &Default::default())
+    }
+}
+
+impl<const WINDOW: u8, const LOOKAHEAD: u8> Sizes for FixedDecoder<WINDOW, LOOKAHEAD> {
+    #[inline]
+    fn config(&self) -> Config {
+        Self::CONFIG
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FixedDecoder, FixedEncoder};
+    use crate::{decode, encode, Config};
+
+    #[test]
+    fn matches_runtime_config() {
+        let src: [u8; 500] = core::array::from_fn(|i| (i * i % 41 % 11) as u8);
+        let mut dst1 = [0; 600];
+        let mut dst2 = [0; 600];
+        let mut dst3 = [0; 500];
+        let encoded = FixedEncoder::<11, 4>::encode(&src, &mut dst1).unwrap();
+        assert_eq!(encoded, encode(&src, &mut dst2, &Config::DEFAULT).unwrap());
+        assert_eq!(
+            FixedDecoder::<11, 4>::decode(encoded, &mut dst3).unwrap(),
+            src
+        );
+        let encoded = FixedEncoder::<4, 3>::encode(&src, &mut dst1).unwrap();
+        let cfg = FixedDecoder::<4, 3>::CONFIG;
+        assert_eq!(cfg, Config::new(4, 3).unwrap());
+        assert_eq!(decode(encoded, &mut dst3, &cfg).unwrap(), src);
+        assert_eq!(FixedDecoder::<4, 3>::WINDOW_SIZE, 16);
+    }
+}
@@ -0,0 +1,135 @@
+use super::{decode, encode, Config, DecodeError, EncodeError};
+
+// High nibble identifies the frame format, low nibble is the version, with
+// its top bit flagging data stored raw
+const FRAME_MAGIC: u8 = 0xa0;
+const FRAME_VERSION: u8 = 0;
+const FRAME_RAW: u8 = 0x08;
+const HEADER_SIZE: usize = 2;
+
+/// Compression call like `encode()`, prepending a 2 byte header that
+/// records the configuration, so that `decode_framed()` needs no `Config`.
+/// Input that would compress to more than its own size, like a record of
+/// a few bytes, each costing a 9 bit literal, is stored raw instead, with
+/// a flag in the header, so the frame is never more than the 2 header
+/// bytes longer than the input. Frames stored raw need a `decode_framed()`
+/// recognizing the flag, which versions before it reject as a bad header
+pub fn encode_framed<'a>(
+    input: &[u8],
+    output: &'a mut [u8],
+    cfg: &Config,
+) -> Result<&'a [u8], EncodeError> {
+    if output.len() < HEADER_SIZE {
+        return Err(EncodeError::OutputFull);
+    }
+    let (header, rest) = output.split_at_mut(HEADER_SIZE);
+    header[0] = FRAME_MAGIC | FRAME_VERSION;
+    header[1] = cfg.to_byte();
+    // Only compressed data up to the size of the input is of use
+    let limit = rest.len().min(input.len());
+    let len = match encode(input, &mut rest[..limit], cfg).map(|encoded| encoded.len()) {
+        Ok(len) => len,
+        Err(EncodeError::OutputFull) if rest.len() >= input.len() => {
+            rest[..input.len()].copy_from_slice(input);
+            header[0] |= FRAME_RAW;
+            input.len()
+        }
+        Err(err) => return Err(err),
+    };
+    Ok(&output[..HEADER_SIZE + len])
+}
+
+/// Decompression call for data produced by `encode_framed()`, returning
+/// the decompressed data along with the configuration read from the header.
+/// Data stored raw is copied to `output`. An unknown magic or version, or
+/// an invalid configuration, results in `DecodeError::BadHeader`
+pub fn decode_framed<'a>(
+    input: &[u8],
+    output: &'a mut [u8],
+) -> Result<(&'a [u8], Config), DecodeError> {
+    if input.len() < HEADER_SIZE || input[0] & !FRAME_RAW != FRAME_MAGIC | FRAME_VERSION {
+        return Err(DecodeError::BadHeader);
+    }
+    let cfg = Config::from_byte(input[1]).map_err(|_| DecodeError::BadHeader)?;
+    let data = &input[HEADER_SIZE..];
+    if input[0] & FRAME_RAW != 0 {
+        let dst = output
+            .get_mut(..data.len())
+            .ok_or(DecodeError::OutputFull {
+                written: 0,
+                input_bit: HEADER_SIZE * 8,
+            })?;
+        dst.copy_from_slice(data);
+        return Ok((dst, cfg));
+    }
+    let decoded = decode(data, output, &cfg).map_err(|err| err.offset_input(HEADER_SIZE))?;
+    Ok((decoded, cfg))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode_framed, encode_framed};
+    use crate::{Config, DecodeError, EncodeError};
+
+    #[test]
+    fn round_trip() {
+        let src = b"abcabcabcabcabcabcabcabc";
+        let mut dst1 = [0; 100];
+        let mut dst2 = [0; 100];
+        for (window, lookahead) in [(4, 3), (11, 4), (16, 12)] {
+            let cfg = Config::new(window, lookahead).unwrap();
+            let encoded = encode_framed(src, &mut dst1, &cfg).unwrap();
+            let (decoded, detected) = decode_framed(encoded, &mut dst2).unwrap();
+            assert_eq!(decoded, src);
+            assert_eq!(detected.window_sz2(), window);
+            assert_eq!(detected.lookahead_sz2(), lookahead);
+        }
+    }
+
+    #[test]
+    fn raw_fallback() {
+        let cfg = Config::default();
+        let mut dst1 = [0; 100];
+        let mut dst2 = [0; 100];
+        // Literals alone take 9 bits per byte
+        for src in [&b"a"[..], b"abcdefg", b"0123456789abcdef"] {
+            let encoded = encode_framed(src, &mut dst1, &cfg).unwrap();
+            assert_eq!(encoded.len(), 2 + src.len());
+            assert_eq!(&encoded[..2], [0xa8, 0xa3]);
+            let (decoded, detected) = decode_framed(encoded, &mut dst2).unwrap();
+            assert_eq!((decoded, detected), (src, cfg));
+            let res = decode_framed(encoded, &mut dst2[..src.len() - 1]);
+            let full = DecodeError::OutputFull {
+                written: 0,
+                input_bit: 16,
+            };
+            assert_eq!(res, Err(full));
+        }
+        // Compressible and empty input is compressed
+        let src = b"abcabcabcabcabcabcabcabc";
+        let encoded = encode_framed(src, &mut dst1, &cfg).unwrap();
+        assert_eq!(encoded[0], 0xa0);
+        let encoded = encode_framed(b"", &mut dst1, &cfg).unwrap();
+        assert_eq!(encoded, [0xa0, 0xa3]);
+        // Raw data needs room for all of the input
+        let res = encode_framed(b"abcdefg", &mut dst1[..8], &cfg);
+        assert_eq!(res, Err(EncodeError::OutputFull));
+        let encoded = encode_framed(b"abcdefg", &mut dst1[..9], &cfg).unwrap();
+        assert_eq!(encoded.len(), 9);
+    }
+
+    #[test]
+    fn bad_header() {
+        let mut dst = [0; 100];
+        for src in [
+            &[0xa0][..],
+            &[0xb0, 0xa3, 0x00],
+            &[0xa1, 0xa3],
+            &[0xa9, 0xa3],
+            &[0xa0, 0x33],
+        ] {
+            let res = decode_framed(src, &mut dst);
+            assert!(matches!(res, Err(DecodeError::BadHeader)));
+        }
+    }
+}
@@ -0,0 +1,365 @@
+use super::{Config, DecodeError, StreamDecoder, StreamEncoder};
+use std::io::{self, Read, Write};
+use std::vec;
+use std::vec::Vec;
+
+const INPUT_BUFFER_SIZE: usize = 1024;
+const OUTPUT_BUFFER_SIZE: usize = 1024;
+
+fn decode_error(err: DecodeError) -> io::Error {
+    let kind = match err {
+        DecodeError::OutputFull { .. } => io::ErrorKind::WriteZero,
+        DecodeError::InvalidConfig => io::ErrorKind::InvalidInput,
+        DecodeError::InputTooShort => io::ErrorKind::UnexpectedEof,
+        DecodeError::IllegalBackref { .. }
+        | DecodeError::ChecksumMismatch
+        | DecodeError::BadHeader
+        | DecodeError::LikelyConfigMismatch
+        | DecodeError::OutputLimitExceeded => io::ErrorKind::InvalidData,
+    };
+    io::Error::new(kind, err)
+}
+
+/// Adapter that decompresses data read from an inner reader on the fly
+pub struct HeatshrinkReader<R> {
+    inner: R,
+    decoder: StreamDecoder<Vec<u8>>,
+    buf: Vec<u8>,
+    pos: usize,
+    len: usize,
+    eof: bool,
+}
+
+impl<R: Read> HeatshrinkReader<R> {
+    /// Creates a new reader decompressing the data from `inner`
+    pub fn new(inner: R, cfg: &Config) -> Self {
+        let window = vec![0; 1 << cfg.window_sz2 as usize];
+        HeatshrinkReader {
+            inner,
+            decoder: StreamDecoder::new(window, cfg),
+            buf: vec![0; INPUT_BUFFER_SIZE],
+            pos: 0,
+            len: 0,
+            eof: false,
+        }
+    }
+
+    /// Returns a reference to the inner reader
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner reader
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Consumes the adapter, returning the inner reader.
+    /// Compressed data that was read but not yet decoded is lost
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    fn fill_buf(&mut self) -> io::Result<()> {
+        loop {
+            match self.inner.read(&mut self.buf) {
+                Ok(n) => {
+                    self.pos = 0;
+                    self.len = n;
+                    self.eof = n == 0;
+                    return Ok(());
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl<R: Read> Read for HeatshrinkReader<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if out.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let n = self.decoder.poll(out).map_err(decode_error)?;
+            if n > 0 {
+                return Ok(n);
+            }
+            if self.pos == self.len {
+                if self.eof {
+                    return Ok(0);
+                }
+                self.fill_buf()?;
+            }
+            self.pos += self.decoder.sink(&self.buf[self.pos..self.len]);
+        }
+    }
+}
+
+/// Adapter that compresses data written to it on the fly, passing the
+/// compressed data on to an inner writer
+///
+/// The stream is terminated by `finish()`, or when the adapter is dropped.
+/// Errors are ignored when finishing on drop, so calling `finish()` is recommended
+pub struct HeatshrinkWriter<W: Write> {
+    inner: Option<W>,
+    encoder: StreamEncoder<Vec<u8>>,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> HeatshrinkWriter<W> {
+    /// Creates a new writer compressing data into `inner`
+    pub fn new(inner: W, cfg: &Config) -> Self {
+        let size = (2 << cfg.window_sz2 as usize) + (1 << cfg.lookahead_sz2 as usize);
+        HeatshrinkWriter {
+            inner: Some(inner),
+            encoder: StreamEncoder::new(vec![0; size], cfg),
+            buf: vec![0; OUTPUT_BUFFER_SIZE],
+        }
+    }
+
+    /// Returns a reference to the inner writer
+    pub fn get_ref(&self) -> &W {
+        self.inner.as_ref().unwrap()
+    }
+
+    /// Returns a mutable reference to the inner writer.
+    /// Writing to it directly will corrupt the compressed stream
+    pub fn get_mut(&mut self) -> &mut W {
+        self.inner.as_mut().unwrap()
+    }
+
+    /// Compresses all remaining data, writes the final padded byte,
+    /// and returns the inner writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.try_finish()?;
+        Ok(self.inner.take().unwrap())
+    }
+
+    fn try_finish(&mut self) -> io::Result<()> {
+        // Drain before checking, so that a failed encoder is reported
+        loop {
+            self.drain()?;
+            if self.encoder.finish() {
+                break;
+            }
+        }
+        self.get_mut().flush()
+    }
+
+    // Passes all compressed data the encoder is able to produce to the inner writer
+    fn drain(&mut self) -> io::Result<()> {
+        loop {
+            let n = self.encoder.poll(&mut self.buf);
+            if let Some(err) = self.encoder.error() {
+                return Err(io::Error::other(err));
+            }
+            if n == 0 {
+                return Ok(());
+            }
+            self.inner.as_mut().unwrap().write_all(&self.buf[..n])?;
+        }
+    }
+}
+
+impl<W: Write> Write for HeatshrinkWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if data.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let n = self.encoder.sink(data);
+            self.drain()?;
+            if n > 0 {
+                return Ok(n);
+            }
+        }
+    }
+
+    /// Writes all data written so far to the inner writer, using
+    /// `StreamEncoder::partial_flush()`, except for up to 7 bits of the
+    /// last symbol, since padding the stream here would corrupt it
+    fn flush(&mut self) -> io::Result<()> {
+        self.encoder.partial_flush();
+        self.drain()?;
+        self.get_mut().flush()
+    }
+}
+
+impl<W: Write> Drop for HeatshrinkWriter<W> {
+    fn drop(&mut self) {
+        if self.inner.is_some() {
+            let _ = self.try_finish();
+        }
+    }
+}
+
+/// Compresses everything read from `src` into `dst`, until `src` reaches
+/// its end. Only the buffers of the streaming encoder are held in memory,
+/// so this handles files and sockets of any size. The final padded byte is
+/// written and `dst` flushed before returning
+pub fn encode_reader<R: Read, W: Write>(mut src: R, dst: W, cfg: &Config) -> io::Result<()> {
+    let mut writer = HeatshrinkWriter::new(dst, cfg);
+    io::copy(&mut src, &mut writer)?;
+    writer.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{encode_reader, HeatshrinkReader, HeatshrinkWriter};
+    use crate::{decode, encode, Config};
+    use std::io::{self, Read, Write};
+    use std::vec;
+    use std::vec::Vec;
+
+    /// Reader handing out at most `step` bytes per call
+    struct Trickle<'a> {
+        data: &'a [u8],
+        step: usize,
+    }
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.step.min(buf.len()).min(self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    fn sample() -> Vec<u8> {
+        (0..5000u32).map(|i| (i * i % 251 % 13) as u8).collect()
+    }
+
+    #[test]
+    fn read_to_end() {
+        let src = sample();
+        let cfg = Config::default();
+        let mut compressed = vec![0; 2 * src.len()];
+        let compressed = encode(&src, &mut compressed, &cfg).unwrap();
+        for step in [1, 3, 4096] {
+            let inner = Trickle {
+                data: compressed,
+                step,
+            };
+            let mut decoded = Vec::new();
+            HeatshrinkReader::new(inner, &cfg)
+                .read_to_end(&mut decoded)
+                .unwrap();
+            assert_eq!(decoded, src);
+        }
+    }
+
+    #[test]
+    fn small_reads() {
+        let src = sample();
+        let cfg = Config::new(8, 4).unwrap();
+        let mut compressed = vec![0; 2 * src.len()];
+        let compressed = encode(&src, &mut compressed, &cfg).unwrap();
+        let mut reader = HeatshrinkReader::new(compressed, &cfg);
+        let mut decoded = Vec::new();
+        let mut buf = [0; 7];
+        loop {
+            let n = reader.read(&mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            decoded.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(decoded, src);
+    }
+
+    /// Writer accepting at most `step` bytes per call
+    struct Sluggish {
+        data: Vec<u8>,
+        step: usize,
+    }
+
+    impl Write for Sluggish {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let n = self.step.min(buf.len());
+            self.data.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn write_matches_encode() {
+        let src = sample();
+        let cfg = Config::default();
+        let mut expected = vec![0; 2 * src.len()];
+        let expected = encode(&src, &mut expected, &cfg).unwrap();
+        for step in [1, 5, 4096] {
+            let inner = Sluggish {
+                data: Vec::new(),
+                step,
+            };
+            let mut writer = HeatshrinkWriter::new(inner, &cfg);
+            for part in src.chunks(step) {
+                writer.write_all(part).unwrap();
+            }
+            assert_eq!(writer.finish().unwrap().data, expected);
+        }
+    }
+
+    #[test]
+    fn flush_passes_on_data() {
+        let src = sample();
+        let cfg = Config::default();
+        let mut decoded = vec![0; src.len()];
+        let mut writer = HeatshrinkWriter::new(Vec::new(), &cfg);
+        let mut written = 0;
+        for part in src.chunks(700) {
+            writer.write_all(part).unwrap();
+            writer.flush().unwrap();
+            written += part.len();
+            // All but the bits of the last symbol reach the inner writer
+            let out = decode(writer.get_ref(), &mut decoded, &cfg).unwrap();
+            assert!(out.len() <= written && out.len() + 16 >= written);
+            assert_eq!(out, &src[..out.len()]);
+        }
+        // Which leaves only the final padded byte to finish()
+        let flushed = writer.get_ref().len();
+        let compressed = writer.finish().unwrap();
+        assert!(compressed.len() <= flushed + 1);
+        assert_eq!(decode(&compressed, &mut decoded, &cfg).unwrap(), src);
+    }
+
+    #[test]
+    fn reader_to_writer() {
+        let src = sample();
+        let cfg = Config::default();
+        let mut expected = vec![0; 2 * src.len()];
+        let expected = encode(&src, &mut expected, &cfg).unwrap();
+        for step in [1, 7, 4096] {
+            let mut dst = Sluggish {
+                data: Vec::new(),
+                step,
+            };
+            let reader = Trickle { data: &src, step };
+            encode_reader(reader, &mut dst, &cfg).unwrap();
+            assert_eq!(dst.data, expected);
+        }
+    }
+
+    #[test]
+    fn finish_on_drop() {
+        let src = sample();
+        let cfg = Config::new(8, 4).unwrap();
+        let mut compressed = Vec::new();
+        HeatshrinkWriter::new(&mut compressed, &cfg)
+            .write_all(&src)
+            .unwrap();
+        let mut decoded = Vec::new();
+        HeatshrinkReader::new(&compressed[..], &cfg)
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, src);
+    }
+}
@@ -8,69 +8,364 @@
 //! described here <https://github.com/atomicobject/heatshrink>
 //! and here <https://spin.atomicobject.com/2013/03/14/heatshrink-embedded-data-compression/>
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+
+#[cfg(feature = "parallel")]
+mod blocks;
+#[cfg(feature = "checksum")]
+mod checksum;
 mod decoder;
+#[cfg(feature = "embedded-io")]
+mod embedded;
 mod encoder;
+mod fixed;
+mod framed;
+#[cfg(feature = "std")]
+mod io;
+mod resync;
+mod sink;
+mod stored;
+mod stream_decoder;
+mod stream_encoder;
+mod window;
 
-pub use decoder::{decode, DecodeError};
-pub use encoder::{encode, EncodeError};
+#[cfg(feature = "parallel")]
+pub use blocks::{decode_blocks, encode_blocks};
+#[cfg(feature = "checksum")]
+pub use checksum::{decode_with_crc, encode_with_crc};
+#[cfg(feature = "heapless")]
+pub use decoder::decode_heapless;
+#[cfg(feature = "alloc")]
+pub use decoder::decode_owned;
+#[cfg(feature = "trace")]
+pub use decoder::DecodeEvent;
+pub use decoder::{
+    decode, decode_in_place, decode_into, decode_len, decode_limited, decode_with_dict,
+    decode_with_history, decode_with_options, decode_with_stats, decoded_len, in_place_len,
+    looks_compressed, validate, validate_with_options, DecodeError, DecodeOptions, DecodeStats,
+    HeatshrinkDecoder,
+};
+#[cfg(feature = "embedded-io")]
+pub use embedded::{EmbeddedError, EmbeddedReader, EmbeddedWriter};
+#[cfg(feature = "heapless")]
+pub use encoder::encode_heapless;
+#[cfg(feature = "alloc")]
+pub use encoder::encode_optimal;
+pub use encoder::{
+    encode, encode_bound_exact, encode_bounded, encode_len, encode_str, encode_vectored,
+    encode_with_dict, encode_with_history, encode_with_options, encode_with_scratch,
+    encode_with_stats, EncodeError, EncodeOptions, EncodeStats, HeatshrinkEncoder,
+};
+pub use fixed::{FixedDecoder, FixedEncoder};
+pub use framed::{decode_framed, encode_framed};
+#[cfg(feature = "std")]
+pub use io::{encode_reader, HeatshrinkReader, HeatshrinkWriter};
+pub use resync::{decode_resync, encode_resync, MAX_RESYNC_INTERVAL};
+pub use sink::{
+    decode_from_source, decode_to_fmt, decode_to_sink, decode_with_callback, encode_to_sink,
+    encode_with_callback, BufferFull, ByteSink, ByteSource, SinkError,
+};
+pub use stored::{compress_if_smaller, decompress_if_smaller, CompressResult};
+pub use stream_decoder::{decode_iter, DecodeIter, PollResult, StreamDecoder};
+pub use stream_encoder::{PushEncoder, StreamEncoder};
+pub use window::Window;
 
 /// Structure holding the configuration parameters
 /// These can be tuned to improve compression ratio
 /// But they must be the same for encode() & decode()
 /// calls to be able to produce the original data
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct Config {
     pub(crate) window_sz2: u8,
     pub(crate) lookahead_sz2: u8,
 }
 
+/// Errors that may be encountered when building a configuration
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    /// The window size is above `Config::MAX_WINDOW_SZ2`
+    WindowTooLarge,
+    /// The window size is below `Config::MIN_WINDOW_SZ2`
+    WindowTooSmall,
+    /// The lookahead size is above `Config::MAX_LOOKAHEAD_SZ2`
+    LookaheadTooLarge,
+    /// The lookahead size is below `Config::MIN_LOOKAHEAD_SZ2`
+    LookaheadTooSmall,
+    /// The lookahead is not smaller than the window
+    LookaheadExceedsWindow,
+}
+
+impl core::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let msg = match self {
+            ConfigError::WindowTooLarge => "Window is too large",
+            ConfigError::WindowTooSmall => "Window is too small",
+            ConfigError::LookaheadTooLarge => "Lookahead is too large",
+            ConfigError::LookaheadTooSmall => "Lookahead is too small",
+            ConfigError::LookaheadExceedsWindow => "Lookahead must be smaller than window",
+        };
+        f.write_str(msg)
+    }
+}
+
+impl core::error::Error for ConfigError {}
+
+/// Any error of this crate, for callers that both compress and decompress
+/// and want a single error type to propagate with `?`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HeatshrinkError {
+    /// Building a configuration failed
+    Config(ConfigError),
+    /// Compressing failed
+    Encode(EncodeError),
+    /// Decompressing failed
+    Decode(DecodeError),
+}
+
+impl core::fmt::Display for HeatshrinkError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            HeatshrinkError::Config(err) => err.fmt(f),
+            HeatshrinkError::Encode(err) => err.fmt(f),
+            HeatshrinkError::Decode(err) => err.fmt(f),
+        }
+    }
+}
+
+impl core::error::Error for HeatshrinkError {}
+
+impl From<ConfigError> for HeatshrinkError {
+    fn from(err: ConfigError) -> Self {
+        HeatshrinkError::Config(err)
+    }
+}
+
+impl From<EncodeError> for HeatshrinkError {
+    fn from(err: EncodeError) -> Self {
+        HeatshrinkError::Encode(err)
+    }
+}
+
+impl From<DecodeError> for HeatshrinkError {
+    fn from(err: DecodeError) -> Self {
+        HeatshrinkError::Decode(err)
+    }
+}
+
 impl Default for Config {
     fn default() -> Self {
-        let window_sz2 = 11;
-        let lookahead_sz2 = 4;
+        Config::DEFAULT
+    }
+}
+
+impl Config {
+    /// The default configuration, with a window of 11 and lookahead of 4
+    pub const DEFAULT: Config = Config::new_unchecked(11, 4);
+
+    /// The smallest window size accepted, as the lookahead must be smaller
+    pub const MIN_WINDOW_SZ2: u8 = 2;
+
+    /// The largest window size accepted
+    pub const MAX_WINDOW_SZ2: u8 = 16;
+
+    /// The smallest lookahead size accepted
+    pub const MIN_LOOKAHEAD_SZ2: u8 = 1;
+
+    /// The largest lookahead size accepted, as the window must be larger
+    pub const MAX_LOOKAHEAD_SZ2: u8 = 15;
+
+    /// A configuration for English text, with a window of 13 and lookahead
+    /// of 4. Text repeats words and phrases over longer distances than the
+    /// default window covers, while longer lookaheads rarely pay off. On
+    /// license texts and manuals of 20 - 600 kB this shrinks the output by
+    /// 10 - 15% compared to `DEFAULT`, 42% instead of 47% of the GFDL
+    /// for instance. The decoder needs an 8 kB window when streaming
+    pub const fn text() -> Self {
+        Config::new_unchecked(13, 4)
+    }
+
+    /// Recommends a configuration for data resembling `sample`. This is a
+    /// heuristic rather than the best configuration, which only trying them
+    /// all would find. The window is the largest one the sample fills,
+    /// between 8 and 13, as a window beyond the data only costs index bits,
+    /// and the lookahead fits the typical length of the matches that a quick
+    /// scan of the sample finds. Data with few matches gets a window of 8,
+    /// as all configurations compress it about equally badly
+    pub fn for_data(sample: &[u8]) -> Self {
+        let bits = usize::BITS - sample.len().leading_zeros();
+        let window = bits.saturating_sub(1).clamp(8, 13) as u8;
+        // Scan with the longest lookahead considered, and few candidates
+        let probe = Config::new_unchecked(window, 8.min(window - 1));
+        let opts = EncodeOptions::new().with_max_chain_len(32);
+        let (backrefs, matched) = encoder::scan(sample, &probe, &opts);
+        if backrefs == 0 || matched < sample.len() / 8 {
+            return Config::new_unchecked(8, 4);
+        }
+        // One more bit than the average needs, to also fit the longer ones
+        let average = matched.div_ceil(backrefs);
+        let lookahead = usize::BITS - (average - 1).leading_zeros() + 1;
+        Config::new_unchecked(window, (lookahead as u8).clamp(3, probe.lookahead_sz2))
+    }
+
+    /// Creates a configuration without validating it, so that it can be
+    /// used in constant expressions like
+    /// `const CFG: Config = Config::new_unchecked(11, 4);`
+    ///
+    /// The caller is responsible for passing values `new()` would accept.
+    /// Compressing or decompressing with an invalid configuration may panic
+    /// or produce corrupt data
+    pub const fn new_unchecked(window_sz2: u8, lookahead_sz2: u8) -> Self {
         Config {
             window_sz2,
             lookahead_sz2,
         }
     }
-}
 
-impl Config {
-    /// Creates a new configuration object with default values
-    pub fn new(window_sz2: u8, lookahead_sz2: u8) -> Result<Self, &'static str> {
-        Config::default()
-            .with_window(window_sz2)
-            .and_then(|c| c.with_lookahead(lookahead_sz2))
-    }
-
-    /// Modifies the configuration with a desired window size ( in range 1 - 16 )
-    pub fn with_window(mut self, window_sz2: u8) -> Result<Self, &'static str> {
-        if window_sz2 > 16 {
-            Err("Window is too large")
-        } else if window_sz2 == 0 {
-            Err("Window is too small")
-        } else {
-            self.window_sz2 = window_sz2;
-            Ok(self)
+    /// Creates a new configuration object with the given window and lookahead
+    pub fn new(window_sz2: u8, lookahead_sz2: u8) -> Result<Self, ConfigError> {
+        Config {
+            window_sz2,
+            lookahead_sz2,
+        }
+        .validate()
+    }
+
+    /// Starts building a configuration from the sizes of `DEFAULT`, which is
+    /// only validated as a whole by `ConfigBuilder::build()`
+    pub const fn builder() -> ConfigBuilder {
+        ConfigBuilder {
+            window_sz2: Config::DEFAULT.window_sz2,
+            lookahead_sz2: Config::DEFAULT.lookahead_sz2,
         }
     }
 
-    /// Modifies the configuration with the desired lookahead
-    pub fn with_lookahead(mut self, lookahead_sz2: u8) -> Result<Self, &'static str> {
-        if lookahead_sz2 > 16 {
-            Err("Window is too large")
-        } else if lookahead_sz2 == 0 {
-            Err("Window is too small")
+    /// Modifies the configuration with a desired window size, in the range of
+    /// `MIN_WINDOW_SZ2` - `MAX_WINDOW_SZ2`. The window must be larger than
+    /// the lookahead, so growing both has to start with the window, while
+    /// `builder()` accepts them in any order
+    pub fn with_window(mut self, window_sz2: u8) -> Result<Self, ConfigError> {
+        self.window_sz2 = window_sz2;
+        self.validate()
+    }
+
+    /// Modifies the configuration with the desired lookahead, in the range of
+    /// `MIN_LOOKAHEAD_SZ2` - `MAX_LOOKAHEAD_SZ2`. The lookahead must be
+    /// smaller than the window
+    pub fn with_lookahead(mut self, lookahead_sz2: u8) -> Result<Self, ConfigError> {
+        self.lookahead_sz2 = lookahead_sz2;
+        self.validate()
+    }
+
+    /// Returns the configured window size as a power of 2
+    pub const fn window_sz2(&self) -> u8 {
+        self.window_sz2
+    }
+
+    /// Returns the configured lookahead as a power of 2
+    pub const fn lookahead_sz2(&self) -> u8 {
+        self.lookahead_sz2
+    }
+
+    /// Packs the configuration into one byte, for headers and metadata,
+    /// holding `window_sz2 - 1` in the high nibble and `lookahead_sz2 - 1`
+    /// in the low one. Configurations made with `new_unchecked()` outside
+    /// the valid range do not survive the trip through `from_byte()`
+    pub const fn to_byte(&self) -> u8 {
+        let window = self.window_sz2.wrapping_sub(1) & 0xf;
+        let lookahead = self.lookahead_sz2.wrapping_sub(1) & 0xf;
+        window << 4 | lookahead
+    }
+
+    /// Unpacks a configuration packed by `to_byte()`, validating it like
+    /// `new()`, so that a window of 1 or a lookahead of 16 are rejected, as
+    /// is a lookahead that is not smaller than the window
+    pub fn from_byte(byte: u8) -> Result<Self, ConfigError> {
+        Config::new((byte >> 4) + 1, (byte & 0xf) + 1)
+    }
+
+    pub(crate) const fn validate(self) -> Result<Self, ConfigError> {
+        if self.window_sz2 > Config::MAX_WINDOW_SZ2 {
+            Err(ConfigError::WindowTooLarge)
+        } else if self.window_sz2 < Config::MIN_WINDOW_SZ2 {
+            Err(ConfigError::WindowTooSmall)
+        } else if self.lookahead_sz2 > Config::MAX_LOOKAHEAD_SZ2 {
+            Err(ConfigError::LookaheadTooLarge)
+        } else if self.lookahead_sz2 < Config::MIN_LOOKAHEAD_SZ2 {
+            Err(ConfigError::LookaheadTooSmall)
+        } else if self.lookahead_sz2 >= self.window_sz2 {
+            Err(ConfigError::LookaheadExceedsWindow)
         } else {
-            self.lookahead_sz2 = lookahead_sz2;
             Ok(self)
         }
     }
 }
 
+// Window and lookahead sizes as the codec reads them, either from a
+// `Config` at run time, or as constants for `FixedEncoder` and
+// `FixedDecoder`, which the compiler folds into the bit widths and bounds
+pub(crate) trait Sizes: Copy {
+    fn config(&self) -> Config;
+
+    fn window_sz2(&self) -> u8 {
+        self.config().window_sz2
+    }
+
+    fn lookahead_sz2(&self) -> u8 {
+        self.config().lookahead_sz2
+    }
+}
+
+impl Sizes for Config {
+    #[inline]
+    fn config(&self) -> Config {
+        *self
+    }
+}
+
+/// Builder of a `Config`, from `Config::builder()`, which sets the window
+/// and lookahead in any order and validates them together in `build()`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ConfigBuilder {
+    window_sz2: u8,
+    lookahead_sz2: u8,
+}
+
+impl ConfigBuilder {
+    /// Sets the window size as a power of 2, in the range of
+    /// `Config::MIN_WINDOW_SZ2` - `Config::MAX_WINDOW_SZ2`
+    pub const fn window(mut self, window_sz2: u8) -> Self {
+        self.window_sz2 = window_sz2;
+        self
+    }
+
+    /// Sets the lookahead as a power of 2, in the range of
+    /// `Config::MIN_LOOKAHEAD_SZ2` - `Config::MAX_LOOKAHEAD_SZ2`
+    pub const fn lookahead(mut self, lookahead_sz2: u8) -> Self {
+        self.lookahead_sz2 = lookahead_sz2;
+        self
+    }
+
+    /// Validates the sizes like `Config::new()`, checking that the lookahead
+    /// is smaller than the window
+    pub const fn build(self) -> Result<Config, ConfigError> {
+        Config::new_unchecked(self.window_sz2, self.lookahead_sz2).validate()
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use super::{decoder, encoder, Config};
+    extern crate std;
+
+    use super::{
+        decoder, encoder, Config, ConfigError, DecodeError, DecodeOptions, DecodeStats,
+        EncodeError, EncodeOptions, EncodeStats, HeatshrinkDecoder, HeatshrinkEncoder,
+        StreamDecoder, StreamEncoder,
+    };
+    use proptest::prelude::Just;
+    use proptest::strategy::Strategy;
 
     fn compare(src: &[u8]) {
         let mut dst1 = [0; 100];
@@ -150,6 +445,64 @@ mod test {
         assert!(encoder::encode(&src, &mut out, &cfg).is_err());
     }
 
+    #[test]
+    fn short_decode_buffer() {
+        let src = b"abcdefgh abcdefgh abcdefgh";
+        let cfg = Config::new(8, 4).unwrap();
+        let mut dst1 = [0; 40];
+        let mut dst2 = [0; 12];
+        let encoded = encoder::encode(src, &mut dst1, &cfg).unwrap();
+        match decoder::decode(encoded, &mut dst2, &cfg) {
+            // The backref following the first 9 literals does not fit
+            Err(DecodeError::OutputFull { written, input_bit }) => {
+                assert_eq!(written, 9);
+                assert_eq!(input_bit, 81);
+                assert_eq!(dst2[..written], src[..written]);
+            }
+            res => panic!("Unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn short_flush_buffer() {
+        // 3 literals fill 3 bytes, leaving 3 bits for the final padded byte
+        let src = b"abc";
+        let cfg: Config = Default::default();
+        let mut dst = [0; 4];
+        let res = encoder::encode(src, &mut dst[..3], &cfg);
+        assert!(matches!(res, Err(EncodeError::OutputFull)));
+        assert_eq!(encoder::encode(src, &mut dst, &cfg).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn error_display() {
+        extern crate std;
+        use std::string::ToString;
+        assert_eq!(EncodeError::OutputFull.to_string(), "Output is full");
+        let full = |written, input_bit| DecodeError::OutputFull { written, input_bit };
+        let err = full(3, 20);
+        assert_eq!(err, full(3, 20));
+        assert_ne!(err, full(4, 20));
+        assert_ne!(err, full(3, 21));
+        assert_eq!(err.to_string(), "Output is full");
+        assert_eq!(DecodeError::BadHeader.to_string(), "Bad frame header");
+    }
+
+    #[test]
+    fn output_tail_untouched() {
+        let src = b"abcdefgh abcdefgh abcdefgh";
+        let cfg = Config::new(8, 4).unwrap();
+        let mut dst1 = [0; 40];
+        let mut dst2 = [0xaa; 40];
+        let encoded = encoder::encode(src, &mut dst1, &cfg).unwrap();
+        assert_eq!(decoder::decode(encoded, &mut dst2, &cfg).unwrap(), src);
+        assert!(dst2[src.len()..].iter().all(|b| *b == 0xaa));
+        let mut dst3 = [0xaa; 20];
+        assert!(decoder::decode(encoded, &mut dst3, &cfg).is_err());
+        assert_eq!(dst3[..9], src[..9]);
+        assert!(dst3[9..].iter().all(|b| *b == 0xaa));
+    }
+
     #[test]
     fn clib_compatibility() {
         let src = hex_literal::hex!("90D4B2B549A408057C003E0100C9811B7CA05F1817C002DA5F04025F0005");
@@ -160,6 +513,712 @@ mod test {
         assert_eq!(decoded, expected);
     }
 
+    #[test]
+    fn clib_compatible_encode() {
+        let src = hex_literal::hex!("215295543402000000000000000000000000000000000000000000000000000000000000000000009302000000000000F202F102F0020000000000002F0400000000000000000000000000000000000000000000");
+        let expected =
+            hex_literal::hex!("90D4B2B549A408057C003E0100C9811B7CA05F1817C002DA5F04025F0005");
+        let cfg = Config::new(11, 4).unwrap();
+        let opts = EncodeOptions::new().with_compat(true);
+        let mut dst1 = [0; 100];
+        let mut dst2 = [0; 100];
+        let encoded = encoder::encode_with_options(&src, &mut dst1, &cfg, &opts).unwrap();
+        assert_eq!(encoded, expected);
+        let decoded = decoder::decode(encoded, &mut dst2, &cfg).unwrap();
+        assert_eq!(decoded, src);
+        // The default encoding differs from the C one only in matching the
+        // zero runs within the data rather than before its start, and was
+        // checked to decode with the C decoder
+        let expected =
+            hex_literal::hex!("90D4B2B549A40A00001E001F00C9811B7CA05F1817C002DA5F04025F0005");
+        let encoded = encoder::encode(&src, &mut dst1, &cfg).unwrap();
+        assert_eq!(encoded, expected);
+        let strict = DecodeOptions::new()
+            .with_zero_window(false)
+            .with_strict(true);
+        let decoded = decoder::decode_with_options(encoded, &mut dst2, &cfg, &strict).unwrap();
+        assert_eq!(decoded, src);
+    }
+
+    // Deterministic generator of test inputs, mixing random literals with
+    // runs of a byte and copies of earlier data. `repeat` out of 256 is the
+    // share of steps producing a run or a copy, and thereby how compressible
+    // the input is
+    struct Corpus {
+        state: u32,
+    }
+
+    impl Corpus {
+        fn new(seed: u32) -> Self {
+            // Xorshift generator, whose state must not be zero
+            Corpus {
+                state: seed.wrapping_mul(0x9e37_79b9) | 1,
+            }
+        }
+
+        fn next(&mut self, range: u32) -> u32 {
+            self.state ^= self.state << 13;
+            self.state ^= self.state >> 17;
+            self.state ^= self.state << 5;
+            self.state % range
+        }
+
+        fn generate(&mut self, len: usize, repeat: u32) -> std::vec::Vec<u8> {
+            let mut out = std::vec::Vec::with_capacity(len);
+            while out.len() < len {
+                if out.is_empty() || self.next(256) >= repeat {
+                    out.push(self.next(256) as u8);
+                } else if self.next(4) == 0 {
+                    // Run lengths spread over 1 - 64, short ones being likelier
+                    let max = 1 + self.next(64);
+                    let run = 1 + self.next(max) as usize;
+                    let byte = *out.last().unwrap();
+                    out.extend(core::iter::repeat_n(byte, run));
+                } else {
+                    let dist = 1 + self.next(out.len().min(4096) as u32) as usize;
+                    for _ in 0..3 + self.next(38) {
+                        out.push(out[out.len() - dist]);
+                    }
+                }
+            }
+            out.truncate(len);
+            out
+        }
+    }
+
+    #[test]
+    fn corpus() {
+        let configs = [(4, 3), (8, 4), (11, 4), (13, 6)];
+        let mut sizes = [0; 4];
+        for (i, repeat) in [0, 64, 160, 240].into_iter().enumerate() {
+            for seed in 0..4 {
+                let src = Corpus::new(seed).generate(2000, repeat);
+                assert_eq!(src, Corpus::new(seed).generate(2000, repeat));
+                let mut dst1 = [0; 2300];
+                let mut dst2 = [0; 2000];
+                for (window, lookahead) in configs {
+                    let cfg = Config::new(window, lookahead).unwrap();
+                    for compat in [false, true] {
+                        let opts = EncodeOptions::new().with_compat(compat);
+                        let encoded =
+                            encoder::encode_with_options(&src, &mut dst1, &cfg, &opts).unwrap();
+                        let decoded = decoder::decode(encoded, &mut dst2, &cfg).unwrap();
+                        assert_eq!(decoded, src, "{} {} {:?}", seed, repeat, cfg);
+                    }
+                }
+                let encoded = encoder::encode(&src, &mut dst1, &Config::default()).unwrap();
+                sizes[i] += encoded.len();
+            }
+        }
+        // Sizes with the default config when the corpus was added, which
+        // must not get worse. Random data expands by the literal tag bits
+        let expected = [8999, 2188, 1469, 1066];
+        for (size, expected) in sizes.iter().zip(expected) {
+            assert!(*size <= expected, "{:?}", sizes);
+        }
+    }
+
+    #[test]
+    fn looks_compressed() {
+        let cfg = Config::default();
+        let mut dst = [0; 2300];
+        for repeat in [0, 64, 160, 240, 256] {
+            for len in [10, 100, 2000] {
+                let src = Corpus::new(len as u32).generate(len, repeat);
+                let encoded = encoder::encode(&src, &mut dst, &cfg).unwrap();
+                assert!(super::looks_compressed(encoded, &cfg), "{} {}", repeat, len);
+                assert!(!super::looks_compressed(&src, &cfg), "{} {}", repeat, len);
+            }
+        }
+        let text = b"It is a truth universally acknowledged, that a single man in \
+            possession of a good fortune, must be in want of a wife.";
+        assert!(!super::looks_compressed(text, &cfg));
+        let encoded = encoder::encode(text, &mut dst, &cfg).unwrap();
+        assert!(super::looks_compressed(encoded, &cfg));
+        // A valid stream of literals, with the entropy of raw data
+        assert!(decoder::validate(&[0xff; 900], &cfg).is_ok());
+        assert!(!super::looks_compressed(&[0xff; 900], &cfg));
+        assert!(!super::looks_compressed(&[], &cfg));
+    }
+
+    #[test]
+    fn incompressible_never_expands() {
+        let src = Corpus::new(1).generate(1000, 0);
+        let literal_size = (src.len() * 9).div_ceil(8);
+        let mut dst = [0; 1200];
+        for (window, lookahead) in [(4, 3), (8, 4), (11, 4), (16, 15)] {
+            let cfg = Config::new(window, lookahead).unwrap();
+            let out = encoder::encode(&src, &mut dst, &cfg).unwrap();
+            assert!(out.len() <= literal_size);
+        }
+    }
+
+    #[test]
+    fn incompressible_round_trip() {
+        // Literal runs of every length end at every bit alignment
+        let mut src = Corpus::new(1).generate(1000, 0);
+        src[500..600].fill(0x55);
+        let cfg = Config::new(8, 4).unwrap();
+        let mut dst1 = [0; 1200];
+        let mut dst2 = [0; 1000];
+        for len in (0..20).chain([599, 1000]) {
+            let encoded = encoder::encode(&src[..len], &mut dst1, &cfg).unwrap();
+            let decoded = decoder::decode(encoded, &mut dst2[..len], &cfg).unwrap();
+            assert_eq!(decoded, &src[..len]);
+        }
+    }
+
+    #[test]
+    fn lazy_text() {
+        let src = b"It is a truth universally acknowledged, that a single man in \
+            possession of a good fortune, must be in want of a wife. However little \
+            known the feelings or views of such a man may be on his first entering \
+            a neighbourhood, this truth is so well fixed in the minds of the \
+            surrounding families, that he is considered as the rightful property \
+            of some one or other of their daughters.";
+        let cfg = Config::new(11, 4).unwrap();
+        let opts = EncodeOptions::new().with_lazy(true);
+        let mut dst1 = [0; 500];
+        let mut dst2 = [0; 500];
+        let mut dst3 = [0; 500];
+        let greedy = encoder::encode(src, &mut dst1, &cfg).unwrap();
+        let lazy = encoder::encode_with_options(src, &mut dst2, &cfg, &opts).unwrap();
+        assert!(lazy.len() < greedy.len());
+        let decoded = decoder::decode(lazy, &mut dst3, &cfg).unwrap();
+        assert_eq!(decoded, &src[..]);
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn optimal_parse() {
+        let mut dst1 = [0; 700];
+        let mut dst2 = [0; 700];
+        let mut dst3 = [0; 600];
+        for (window, lookahead) in [(2, 1), (4, 3), (8, 4), (11, 4), (11, 8)] {
+            let cfg = Config::new(window, lookahead).unwrap();
+            let backref_bits = 1 + window as usize + lookahead as usize;
+            for repeat in [0, 64, 160, 240] {
+                let src = Corpus::new(repeat).generate(600, repeat);
+                let optimal = super::encode_optimal(&src, &mut dst1, &cfg).unwrap();
+                let decoded = decoder::decode(optimal, &mut dst3, &cfg).unwrap();
+                assert_eq!(decoded, src);
+                for lazy in [false, true] {
+                    let opts = EncodeOptions::new().with_lazy(lazy);
+                    let parsed = encoder::encode_with_options(&src, &mut dst2, &cfg, &opts);
+                    assert!(optimal.len() <= parsed.unwrap().len());
+                }
+                // Fewest bits trying every length of the longest matches
+                let maxlen = 1 << lookahead;
+                let mut cost = std::vec![0; src.len() + 1];
+                for pos in (0..src.len()).rev() {
+                    let longest = (1..=pos.min(1 << window))
+                        .map(|dist| {
+                            let end = src.len().min(pos + maxlen);
+                            (pos..end).take_while(|i| src[*i] == src[i - dist]).count()
+                        })
+                        .max()
+                        .unwrap_or(0);
+                    cost[pos] = (1..=longest)
+                        .map(|len| backref_bits + cost[pos + len])
+                        .fold(9 + cost[pos + 1], usize::min);
+                }
+                assert_eq!(optimal.len(), cost[0].div_ceil(8), "{} {:?}", repeat, cfg);
+            }
+        }
+        let cfg = Config::default();
+        assert_eq!(super::encode_optimal(&[], &mut [], &cfg), Ok(&[][..]));
+        let res = super::encode_optimal(b"abc", &mut dst1[..3], &cfg);
+        assert_eq!(res, Err(EncodeError::OutputFull));
+        let res = super::encode_optimal(b"abc", &mut dst1, &Config::new_unchecked(4, 4));
+        assert_eq!(res, Err(EncodeError::InvalidConfig));
+    }
+
+    #[test]
+    fn max_chain_len() {
+        let src = b"abcd abce abcf abcg abch abcd abce abcf abcg abch";
+        let cfg = Config::new(8, 4).unwrap();
+        let mut dst1 = [0; 100];
+        let mut dst2 = [0; 100];
+        let mut dst3 = [0; 100];
+        let full = encoder::encode(src, &mut dst1, &cfg).unwrap();
+        // Only the nearest "a" is examined, which matches just 3 bytes
+        let opts = EncodeOptions::new().with_max_chain_len(1);
+        let bounded = encoder::encode_with_options(src, &mut dst2, &cfg, &opts).unwrap();
+        assert!(bounded.len() > full.len());
+        let decoded = decoder::decode(bounded, &mut dst3, &cfg).unwrap();
+        assert_eq!(decoded, &src[..]);
+        // Without candidates every byte becomes a literal
+        let opts = EncodeOptions::new().with_max_chain_len(0);
+        let literals = encoder::encode_with_options(src, &mut dst2, &cfg, &opts).unwrap();
+        assert_eq!(literals.len(), (src.len() * 9).div_ceil(8));
+    }
+
+    #[test]
+    fn encode_stats() {
+        let src = b"abcabcabcabc";
+        let cfg = Config::new(8, 4).unwrap();
+        let mut dst1 = [0; 20];
+        let mut dst2 = [0; 20];
+        let (out, stats) = encoder::encode_with_stats(src, &mut dst1, &cfg).unwrap();
+        assert_eq!(out, encoder::encode(src, &mut dst2, &cfg).unwrap());
+        let expected = EncodeStats {
+            literals: 3,
+            backrefs: 1,
+            input_len: 12,
+            output_len: 5,
+        };
+        assert_eq!(stats, expected);
+    }
+
+    #[test]
+    fn single_byte_run() {
+        // Overlapping matches at distance 1 cover the run after the first literal
+        let src = [b'x'; 1000];
+        let cfg = Config::new(8, 4).unwrap();
+        let mut dst1 = [0; 200];
+        let mut dst2 = [0; 1100];
+        let (encoded, stats) = encoder::encode_with_stats(&src, &mut dst1, &cfg).unwrap();
+        assert_eq!(stats.literals, 1);
+        assert_eq!(stats.backrefs, 999usize.div_ceil(16));
+        let decoded = decoder::decode(encoded, &mut dst2, &cfg).unwrap();
+        assert_eq!(decoded, src);
+    }
+
+    #[test]
+    fn codec_types() {
+        let src = b"abcabcabcabc";
+        let cfg = Config::new(8, 4).unwrap();
+        let mut dst1 = [0; 20];
+        let mut dst2 = [0; 20];
+        let mut enc = HeatshrinkEncoder::new(src, &mut dst1, &cfg);
+        let len = enc.encode().unwrap().len();
+        assert_eq!(enc.encode().unwrap().len(), len);
+        let mut dec = HeatshrinkDecoder::new(&dst1[..len], &mut dst2, &cfg);
+        assert_eq!(dec.decode().unwrap(), src);
+        // 3 literals and a backref fill exactly 5 bytes
+        assert_eq!(dec.bit_index(), 3 * 9 + 13);
+        assert_eq!(dec.decode().unwrap(), src);
+    }
+
+    #[test]
+    fn min_match_len() {
+        let src = b"abcd abce abcf abcg abch abcd abce abcf abcg abch";
+        let cfg = Config::new(8, 4).unwrap();
+        let mut dst1 = [0; 100];
+        let mut dst2 = [0; 100];
+        let (_, full) = encoder::encode_with_stats(src, &mut dst1, &cfg).unwrap();
+        // The 3 byte "abc" matches turn into literals
+        let opts = EncodeOptions::new().with_min_match_len(4);
+        let biased = encoder::encode_with_options(src, &mut dst1, &cfg, &opts).unwrap();
+        assert!(biased.len() > full.output_len);
+        let decoded = decoder::decode(biased, &mut dst2, &cfg).unwrap();
+        assert_eq!(decoded, &src[..]);
+        // The break-even of 2 bytes still applies below it
+        let opts = EncodeOptions::new().with_min_match_len(1);
+        let out = encoder::encode_with_options(src, &mut dst1, &cfg, &opts).unwrap();
+        assert_eq!(out.len(), full.output_len);
+    }
+
+    #[test]
+    fn tiny_config_padding() {
+        // Backrefs of 4 bits would fit in the 7 bits of padding
+        let src = [0];
+        let cfg = Config::new(2, 1).unwrap();
+        let mut dst1 = [0; 10];
+        let mut dst2 = [0; 10];
+        let encoded = encoder::encode(&src, &mut dst1, &cfg).unwrap();
+        assert_eq!(encoded, [0x80, 0x7f]);
+        let decoded = decoder::decode(encoded, &mut dst2, &cfg).unwrap();
+        assert_eq!(decoded, src);
+    }
+
+    #[test]
+    fn zero_padding() {
+        // Whatever bits precede it, the padding is zero like the C encoder's
+        let mut dst1 = [0; 2300];
+        let mut dst2 = [0; 2300];
+        let mut buffer = [0; 5200];
+        let mut pads = [false; 8];
+        for (window, lookahead) in [(4, 3), (8, 4), (11, 4), (11, 5), (12, 10)] {
+            let cfg = Config::new(window, lookahead).unwrap();
+            let backref_bits = 1 + window as usize + lookahead as usize;
+            for len in 1..40 {
+                let src = Corpus::new(len as u32).generate(len * 37, 160);
+                let (encoded, stats) = encoder::encode_with_stats(&src, &mut dst1, &cfg).unwrap();
+                let bits = stats.literals * 9 + stats.backrefs * backref_bits;
+                let pad_bits = encoded.len() * 8 - bits;
+                assert!(pad_bits < 8);
+                assert_eq!(encoded.last().unwrap() & ((1 << pad_bits) - 1), 0);
+                pads[pad_bits] = true;
+                let mut enc = StreamEncoder::new(&mut buffer[..], &cfg);
+                assert_eq!(enc.sink(&src), src.len());
+                enc.finish();
+                let n = enc.poll(&mut dst2);
+                assert_eq!(&dst2[..n], encoded);
+            }
+        }
+        assert_eq!(pads, [true; 8]);
+    }
+
+    #[test]
+    fn vectored_input() {
+        let header = b"\x01\x02sensor";
+        let payload = b" sensor reading: 42, sensor reading: 43";
+        let mut src = [0; 47];
+        src[..8].copy_from_slice(header);
+        src[8..].copy_from_slice(payload);
+        let cfg = Config::new(8, 4).unwrap();
+        let mut dst1 = [0; 100];
+        let mut dst2 = [0; 100];
+        let mut dst3 = [0; 100];
+        let expected = encoder::encode(&src, &mut dst1, &cfg).unwrap();
+        for split in [0, 1, 8, 30, 47] {
+            let (a, b) = src.split_at(split);
+            let encoded = encoder::encode_vectored(&[a, &[], b], &mut dst2, &cfg).unwrap();
+            assert_eq!(encoded, expected);
+        }
+        let encoded = encoder::encode_vectored(&[header, payload], &mut dst2, &cfg).unwrap();
+        let decoded = decoder::decode(encoded, &mut dst3, &cfg).unwrap();
+        assert_eq!(decoded, src);
+    }
+
+    #[test]
+    fn exact_bound() {
+        let text = b"It is a truth universally acknowledged, that a single man in \
+            possession of a good fortune, must be in want of a wife.";
+        let mut dst = [0; 200];
+        for src in [&text[..], &[], b"a", b"abcdefgh", &[0; 150]] {
+            for cfg in [Config::default(), Config::new(4, 3).unwrap()] {
+                let len = encoder::encode(src, &mut dst, &cfg).unwrap().len();
+                assert_eq!(encoder::encode_bound_exact(src, &cfg).unwrap(), len);
+            }
+        }
+    }
+
+    #[test]
+    fn scratch_index() {
+        // Inputs much longer than the window wrap around the chain
+        let mut src = Corpus::new(1).generate(3000, 0);
+        for b in src.iter_mut() {
+            *b = b"abcd"[*b as usize % 4];
+        }
+        src[1000..1500].fill(0);
+        src.copy_within(..400, 2000);
+        let mut dst1 = [0; 4000];
+        let mut dst2 = [0; 4000];
+        let mut scratch = [0xffff; (1 << 11) + 512];
+        for cfg in [
+            Config::default(),
+            Config::new(4, 3).unwrap(),
+            Config::new(8, 5).unwrap(),
+        ] {
+            let expected = encoder::encode(&src, &mut dst1, &cfg).unwrap();
+            let encoded = encoder::encode_with_scratch(&src, &mut dst2, &mut scratch, &cfg);
+            assert_eq!(encoded.unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn index_threshold() {
+        // The index gives the same output with all options
+        let mut src = [0; 2000];
+        for (i, b) in src.iter_mut().enumerate() {
+            *b = b"abcd"[i * i % 7 % 4];
+        }
+        let cfg = Config::new(8, 4).unwrap();
+        let mut dst1 = [0; 3000];
+        let mut dst2 = [0; 3000];
+        for opts in [
+            EncodeOptions::new().with_lazy(true),
+            EncodeOptions::new().with_max_chain_len(3),
+            EncodeOptions::new().with_compat(true),
+        ] {
+            let naive = opts.with_index_threshold(usize::MAX);
+            let expected = encoder::encode_with_options(&src, &mut dst1, &cfg, &naive).unwrap();
+            let indexed = opts.with_index_threshold(0);
+            let encoded = encoder::encode_with_options(&src, &mut dst2, &cfg, &indexed);
+            assert_eq!(encoded.unwrap(), expected);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn short_scratch() {
+        let mut dst = [0; 10];
+        let mut scratch = [0; 1 << 11];
+        let _ = encoder::encode_with_scratch(b"abc", &mut dst, &mut scratch, &Config::default());
+    }
+
+    #[test]
+    fn text_config() {
+        let text = "It is a truth universally acknowledged, that a single man in \
+            possession of a good fortune, must be in want of a wife.";
+        let mut dst1 = [0; 200];
+        let mut dst2 = [0; 200];
+        let expected = encoder::encode(text.as_bytes(), &mut dst1, &Config::text()).unwrap();
+        let encoded = encoder::encode_str(text, &mut dst2).unwrap();
+        assert_eq!(encoded, expected);
+        let decoded = decoder::decode(expected, &mut dst2, &Config::text()).unwrap();
+        assert_eq!(decoded, text.as_bytes());
+    }
+
+    #[test]
+    fn config_for_data() {
+        let text = b"It is a truth universally acknowledged, that a single man in \
+            possession of a good fortune, must be in want of a wife. ";
+        let mut src = [0; 3000];
+        for (b, t) in src.iter_mut().zip(text.iter().cycle()) {
+            *b = *t;
+        }
+        let cfg = Config::for_data(&src);
+        assert_eq!((cfg.window_sz2(), cfg.lookahead_sz2()), (11, 8));
+        assert!(cfg.validate().is_ok());
+        // Runs of zeros give the longest lookahead the window allows
+        let cfg = Config::for_data(&[0; 200]);
+        assert_eq!((cfg.window_sz2(), cfg.lookahead_sz2()), (8, 7));
+        for src in [&[][..], b"a", b"abcdefgh"] {
+            let cfg = Config::for_data(src);
+            assert_eq!((cfg.window_sz2(), cfg.lookahead_sz2()), (8, 4));
+        }
+    }
+
+    #[test]
+    fn config_eq() {
+        use std::collections::HashSet;
+        let cfg = Config::new(11, 4).unwrap();
+        assert_eq!(cfg, Config::default());
+        assert_ne!(cfg, Config::new(11, 5).unwrap());
+        assert_eq!(Config::for_data(b"a"), Config::new(8, 4).unwrap());
+        let mut seen = HashSet::new();
+        for (w, l) in [(8, 4), (11, 4), (8, 4), (13, 4)] {
+            seen.insert(Config::new(w, l).unwrap());
+        }
+        assert_eq!(seen.len(), 3);
+        assert!(seen.contains(&Config::text()));
+    }
+
+    #[cfg(feature = "trace")]
+    #[test]
+    fn trace() {
+        use super::DecodeEvent;
+        use std::cell::RefCell;
+        use std::vec::Vec;
+        std::thread_local! {
+            static EVENTS: RefCell<Vec<DecodeEvent>> = const { RefCell::new(Vec::new()) };
+        }
+        fn record(event: DecodeEvent) {
+            EVENTS.with(|events| events.borrow_mut().push(event));
+        }
+        let src = b"abcdefgh abcdefgh";
+        let cfg = Config::new(8, 4).unwrap();
+        let mut dst1 = [0; 40];
+        let mut dst2 = [0; 40];
+        let encoded = encoder::encode(src, &mut dst1, &cfg).unwrap();
+        let opts = DecodeOptions::new().with_trace(record);
+        decoder::decode_with_options(encoded, &mut dst2, &cfg, &opts).unwrap();
+        let events = EVENTS.with(|events| events.take());
+        let literals = events.iter().filter_map(|event| match event {
+            DecodeEvent::Literal { pos, byte } => Some((*pos, *byte)),
+            _ => None,
+        });
+        assert!(literals.eq(src[..9].iter().copied().enumerate()));
+        let backref = DecodeEvent::Backref {
+            pos: 9,
+            distance: 9,
+            count: 8,
+        };
+        assert!(events.contains(&backref));
+        // The fast path for literals reports the states it skips
+        let first = [
+            DecodeEvent::State {
+                name: "HSDS_YIELD_LITERAL",
+                bit_index: 1,
+            },
+            DecodeEvent::Literal { pos: 0, byte: b'a' },
+            DecodeEvent::State {
+                name: "HSDS_TAG_BIT",
+                bit_index: 9,
+            },
+            DecodeEvent::State {
+                name: "HSDS_YIELD_LITERAL",
+                bit_index: 10,
+            },
+        ];
+        assert_eq!(events[..4], first);
+
+        // The streaming decoder takes the same steps, fed all input at once
+        let mut window = [0; 256];
+        let mut dec = StreamDecoder::with_options(&mut window[..], &cfg, &opts);
+        let mut consumed = 0;
+        while consumed < encoded.len() {
+            consumed += dec.sink(&encoded[consumed..]);
+            while dec.poll(&mut dst2).unwrap() > 0 {}
+        }
+        let streamed = EVENTS.with(|events| events.take());
+        let steps = |events: &[DecodeEvent]| -> Vec<DecodeEvent> {
+            events
+                .iter()
+                .filter(|event| {
+                    !matches!(
+                        event,
+                        DecodeEvent::State {
+                            name: "NEED_MORE_DATA",
+                            ..
+                        }
+                    )
+                })
+                .copied()
+                .collect()
+        };
+        assert_eq!(steps(&streamed), steps(&events));
+    }
+
+    #[test]
+    fn encode_bounded() {
+        let text = b"It is a truth universally acknowledged, that a single man in \
+            possession of a good fortune, must be in want of a wife. It is a truth.";
+        let mut packet = [0; 16];
+        let mut dst = [0; 200];
+        for (w, l) in [(8, 4), (11, 4), (11, 8)] {
+            let cfg = Config::new(w, l).unwrap();
+            let mut rest = &text[..];
+            let mut total = 0;
+            while !rest.is_empty() {
+                let (compressed, consumed) =
+                    encoder::encode_bounded(rest, &mut packet, &cfg).unwrap();
+                assert!(consumed > 0);
+                // Only the last packet has room for another literal
+                assert!(consumed == rest.len() || compressed.len() >= 15);
+                let decoded = decoder::decode(compressed, &mut dst[total..], &cfg).unwrap();
+                assert_eq!(decoded, &rest[..consumed]);
+                total += consumed;
+                rest = &rest[consumed..];
+            }
+            assert_eq!(&dst[..total], text);
+        }
+        // With enough room it is the same as encode()
+        let cfg = Config::default();
+        let expected = encoder::encode(text, &mut dst, &cfg).unwrap().to_vec();
+        let mut big = [0; 200];
+        let res = encoder::encode_bounded(text, &mut big, &cfg).unwrap();
+        assert_eq!(res, (&expected[..], text.len()));
+        assert_eq!(
+            encoder::encode_bounded(text, &mut [], &cfg).unwrap(),
+            (&[][..], 0)
+        );
+    }
+
+    #[test]
+    fn unified_error() {
+        use super::HeatshrinkError;
+        fn round_trip(src: &[u8], w: u8, dst: &mut [u8]) -> Result<usize, HeatshrinkError> {
+            let cfg = Config::new(w, 4)?;
+            let (compressed, rest) = dst.split_at_mut(dst.len() / 2);
+            let compressed = encoder::encode(src, compressed, &cfg)?;
+            Ok(decoder::decode(compressed, &mut rest[..src.len() - 1], &cfg)?.len())
+        }
+        let mut dst = [0; 40];
+        let src = b"abcdefghabcdefgh";
+        let err = round_trip(src, 20, &mut dst).unwrap_err();
+        assert_eq!(err, HeatshrinkError::Config(ConfigError::WindowTooLarge));
+        let err = round_trip(src, 8, &mut dst[..20]).unwrap_err();
+        assert_eq!(err, HeatshrinkError::Encode(EncodeError::OutputFull));
+        let err = round_trip(src, 8, &mut dst).unwrap_err();
+        assert!(matches!(
+            err,
+            HeatshrinkError::Decode(DecodeError::OutputFull { .. })
+        ));
+    }
+
+    #[test]
+    fn exact_output_size() {
+        let mut dst1 = [0; 100];
+        let mut dst2 = [0; 100];
+        // Ending with a literal, and with a back-reference
+        for src in [&b"abcabcabcabcx"[..], b"abcabcabcabc", b"x"] {
+            for (w, l) in [(4, 3), (8, 4), (11, 4)] {
+                let cfg = Config::new(w, l).unwrap();
+                let encoded = encoder::encode(src, &mut dst1, &cfg).unwrap();
+                let len = src.len();
+                let decoded = decoder::decode(encoded, &mut dst2[..len], &cfg).unwrap();
+                assert_eq!(decoded, src);
+                let res = decoder::decode(encoded, &mut dst2[..len - 1], &cfg);
+                assert!(
+                    matches!(res, Err(DecodeError::OutputFull { written, .. }) if written < len)
+                );
+            }
+        }
+        let cfg = Config::default();
+        assert_eq!(decoder::decode(&[], &mut [], &cfg).unwrap(), b"");
+        let res = decoder::decode_with_history(&[], &mut dst2[..10], 10, &cfg);
+        assert_eq!(res.unwrap(), b"");
+        let encoded = encoder::encode(b"abc", &mut dst1, &cfg).unwrap();
+        let res = decoder::decode_with_history(encoded, &mut dst2[..4], 10, &cfg);
+        let full = DecodeError::OutputFull {
+            written: 4,
+            input_bit: 0,
+        };
+        assert_eq!(res, Err(full));
+    }
+
+    #[test]
+    fn empty_input() {
+        let cfg = Config::default();
+        let mut dst = [0; 10];
+        assert_eq!(encoder::encode(&[], &mut dst, &cfg).unwrap(), b"");
+        assert_eq!(encoder::encode(&[], &mut [], &cfg).unwrap(), b"");
+        let (encoded, stats) = encoder::encode_with_stats(&[], &mut dst, &cfg).unwrap();
+        assert_eq!(encoded, b"");
+        assert_eq!(stats, EncodeStats::default());
+        assert_eq!(decoder::decode(&[], &mut dst, &cfg).unwrap(), b"");
+        let strict = DecodeOptions::new().with_strict(true);
+        let decoded = decoder::decode_with_options(&[], &mut dst, &cfg, &strict);
+        assert_eq!(decoded.unwrap(), b"");
+
+        let mut buffer = [0; (1 << 11) + (1 << 4)];
+        let mut encoder = StreamEncoder::new(&mut buffer[..], &cfg);
+        assert_eq!(encoder.sink(&[]), 0);
+        assert!(encoder.finish());
+        assert_eq!(encoder.poll(&mut dst), 0);
+        let mut window = [0; 1 << 11];
+        let mut decoder = StreamDecoder::new(&mut window[..], &cfg);
+        assert_eq!(decoder.sink(&[]), 0);
+        assert_eq!(decoder.poll(&mut dst), Ok(0));
+    }
+
+    #[test]
+    fn decode_stats() {
+        let src = b"abcabcabcabcabcabcabcabc";
+        let cfg = Config::new(8, 4).unwrap();
+        let mut dst1 = [0; 40];
+        let mut dst2 = [0; 40];
+        let (encoded, enc) = encoder::encode_with_stats(src, &mut dst1, &cfg).unwrap();
+        let (decoded, stats) = decoder::decode_with_stats(encoded, &mut dst2, &cfg).unwrap();
+        assert_eq!(decoded, src);
+        let expected = DecodeStats {
+            literals: enc.literals,
+            backrefs: enc.backrefs,
+            backref_bytes: src.len() - enc.literals,
+            input_len: enc.output_len,
+            output_len: src.len(),
+        };
+        assert_eq!(stats, expected);
+        assert_eq!((stats.literals, stats.backrefs), (3, 2));
+    }
+
+    #[test]
+    fn byte_aligned_end() {
+        // 8 literals of 9 bits end exactly on a byte boundary
+        let src = *b"abcdefgh";
+        let cfg: Config = Default::default();
+        let mut dst1 = [0; 20];
+        let mut dst2 = [0; 20];
+        let encoded = encoder::encode(&src, &mut dst1, &cfg).unwrap();
+        assert_eq!(encoded.len(), 9);
+        let decoded = decoder::decode(encoded, &mut dst2, &cfg).unwrap();
+        assert_eq!(decoded, src);
+    }
+
     #[test]
     fn random_fuzz_crash_1() {
         let src = [14, 64, 14, 64];
@@ -167,4 +1226,772 @@ mod test {
         let cfg: Config = Default::default();
         let _ = decoder::decode(&src, &mut out, &cfg);
     }
+
+    #[test]
+    fn fuzz_inputs_terminate() {
+        // Decoding stops on any input, as every step consumes input or
+        // produces output. Truncations and tiny outputs hit each state
+        // when it runs out of input or space
+        let inputs: [&[u8]; 4] = [
+            &[14, 64, 14, 64],
+            &[0; 8],
+            &[0xff; 8],
+            &[0x00, 0x01, 0x80, 0x7f],
+        ];
+        let mut out = [0; 64];
+        for src in inputs {
+            for (window, lookahead) in [(4, 3), (8, 4), (11, 4), (12, 10), (15, 8)] {
+                let cfg = Config::new(window, lookahead).unwrap();
+                for len in 0..=src.len() {
+                    for out_len in [0, 1, 2, 64] {
+                        let _ = decoder::decode(&src[..len], &mut out[..out_len], &cfg);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn strict_truncation() {
+        let cfg = Config::new(8, 4).unwrap();
+        let strict = DecodeOptions::new().with_strict(true);
+        let mut dst = [0; 100];
+        // A literal cut after its first byte
+        let res = decoder::decode_with_options(&[0xb0], &mut dst, &cfg, &strict);
+        assert!(matches!(res, Err(DecodeError::InputTooShort)));
+        assert_eq!(decoder::decode(&[0xb0], &mut dst, &cfg).unwrap(), b"");
+
+        let src = b"abcdefgh abcdefgh abcdefgh abcdefgh";
+        let mut dst1 = [0; 100];
+        let encoded = encoder::encode(src, &mut dst1, &cfg).unwrap();
+        let decoded = decoder::decode_with_options(encoded, &mut dst, &cfg, &strict).unwrap();
+        assert_eq!(decoded, src);
+        let mut dst2 = [0; 100];
+        let mut rejected = 0;
+        for len in 0..encoded.len() {
+            let truncated = &encoded[..len];
+            let lenient = decoder::decode(truncated, &mut dst2, &cfg).unwrap();
+            assert!(src.starts_with(lenient));
+            // Cuts leaving fewer than 8 bits of a symbol look like padding
+            match decoder::decode_with_options(truncated, &mut dst, &cfg, &strict) {
+                Ok(decoded) => assert_eq!(decoded, lenient),
+                Err(DecodeError::InputTooShort) => rejected += 1,
+                Err(err) => panic!("{:?}", err),
+            }
+        }
+        assert!(rejected > 0);
+    }
+
+    #[test]
+    fn config_mismatch() {
+        let text = b"It is a truth universally acknowledged, that a single man in \
+            possession of a good fortune, must be in want of a wife. However little \
+            known the feelings or views of such a man may be on his first entering a \
+            neighbourhood, this truth is so well fixed in the minds of the surrounding \
+            families, that he is considered as the rightful property of some one or \
+            other of their daughters.";
+        let mut sparse = [0; 400];
+        for i in (0..400).step_by(37) {
+            sparse[i..i + 3].copy_from_slice(b"abc");
+        }
+        let configs = [
+            (8, 4),
+            (9, 3),
+            (10, 4),
+            (11, 4),
+            (11, 5),
+            (11, 8),
+            (12, 4),
+            (13, 4),
+        ];
+        let compat = EncodeOptions::new().with_compat(true);
+        let checked = DecodeOptions::new().with_mismatch_check(true);
+        let mut dst1 = [0; 1000];
+        let mut dst2 = [0; 10000];
+        let mut rejected = 0;
+        for src in [&text[..], &sparse] {
+            for (w, l) in configs {
+                let cfg = Config::new(w, l).unwrap();
+                let encoded = encoder::encode_with_options(src, &mut dst1, &cfg, &compat).unwrap();
+                for (w2, l2) in configs {
+                    let other = Config::new(w2, l2).unwrap();
+                    let res = decoder::decode_with_options(encoded, &mut dst2, &other, &checked);
+                    if other == cfg {
+                        assert_eq!(res.unwrap(), src);
+                        assert_eq!(decoder::validate(encoded, &cfg), Ok(src.len()));
+                    } else if res == Err(DecodeError::LikelyConfigMismatch) {
+                        rejected += 1;
+                        let res2 = decoder::validate_with_options(encoded, &other, &checked);
+                        assert_eq!(res, res2.map(|_| &[][..]));
+                    }
+                }
+            }
+        }
+        assert!(rejected > 50, "{}", rejected);
+
+        let cfg = Config::new(11, 4).unwrap();
+        let other = Config::new(12, 4).unwrap();
+        let encoded = encoder::encode(text, &mut dst1, &cfg).unwrap();
+        let res = decoder::decode_with_options(encoded, &mut dst2, &other, &checked);
+        assert_eq!(res, Err(DecodeError::LikelyConfigMismatch));
+        let mut window = [0; 1 << 12];
+        let mut dec = StreamDecoder::with_options(&mut window[..], &other, &checked);
+        let mut consumed = 0;
+        let res = loop {
+            consumed += dec.sink(&encoded[consumed..]);
+            match dec.poll(&mut dst2) {
+                Ok(0) if consumed == encoded.len() => break Ok(()),
+                Ok(_) => {}
+                Err(err) => break Err(err),
+            }
+        };
+        assert_eq!(res, Err(DecodeError::LikelyConfigMismatch));
+        assert!(decoder::decode(encoded, &mut dst2, &other).is_ok());
+
+        // Growing zero runs, which compat mode matches in the zero filled
+        // window, trip the check, so valid streams decode by default
+        let mut runs = [0; 228];
+        let mut pos = 0;
+        for run in 1.. {
+            if pos + run >= runs.len() {
+                break;
+            }
+            runs[pos] = run as u8;
+            pos += run + 1;
+        }
+        let cfg = Config::new(8, 4).unwrap();
+        let encoded = encoder::encode_with_options(&runs, &mut dst1, &cfg, &compat).unwrap();
+        let res = decoder::decode_with_options(encoded, &mut dst2, &cfg, &checked);
+        assert_eq!(res, Err(DecodeError::LikelyConfigMismatch));
+        assert_eq!(decoder::decode(encoded, &mut dst2, &cfg).unwrap(), runs);
+        let mut window = [0; 256];
+        let mut dec = StreamDecoder::new(&mut window[..], &cfg);
+        let (mut consumed, mut total) = (0, 0);
+        while consumed < encoded.len() {
+            consumed += dec.sink(&encoded[consumed..]);
+            total += dec.poll(&mut dst2[total..]).unwrap();
+        }
+        assert_eq!(dst2[..total], runs);
+        let mut buf = [0; 300];
+        buf[..encoded.len()].copy_from_slice(encoded);
+        let decoded = decoder::decode_in_place(&mut buf, encoded.len(), &cfg).unwrap();
+        assert_eq!(decoded, runs);
+    }
+
+    #[test]
+    fn validate() {
+        let cfg = Config::new(8, 4).unwrap();
+        let src = b"abcdefgh abcdefgh abcdefgh abcdefgh";
+        let mut dst = [0; 100];
+        let encoded = encoder::encode(src, &mut dst, &cfg).unwrap();
+        assert_eq!(decoder::validate(encoded, &cfg), Ok(src.len()));
+        let strict = DecodeOptions::new().with_strict(true);
+        let mut out = [0; 100];
+        for len in 0..encoded.len() {
+            let truncated = &encoded[..len];
+            let decoded = decoder::decode_with_options(truncated, &mut out, &cfg, &strict);
+            let expected = decoded.map(|decoded| decoded.len());
+            assert_eq!(decoder::validate(truncated, &cfg), expected);
+        }
+        // A backref of distance 1 as the very first symbol
+        assert_eq!(decoder::validate(&[0x00, 0x00], &cfg), Ok(1));
+        // Eight backrefs of distance 256 into the zero window
+        let mut zeros = [0; 13];
+        for bit in (0..104).filter(|bit| (1..9).contains(&(bit % 13))) {
+            zeros[bit / 8] |= 0x80 >> (bit % 8);
+        }
+        assert_eq!(decoder::decode(&zeros, &mut out, &cfg).unwrap(), [0; 8]);
+        assert_eq!(decoder::validate(&zeros, &cfg), Ok(8));
+        let checked = DecodeOptions::new().with_mismatch_check(true);
+        let res = decoder::validate_with_options(&zeros, &cfg, &checked);
+        assert_eq!(res, Err(DecodeError::LikelyConfigMismatch));
+        let opts = DecodeOptions::new().with_zero_window(false);
+        let res = decoder::validate_with_options(&[0x00, 0x00], &cfg, &opts);
+        let illegal = DecodeError::IllegalBackref {
+            input_bit: 0,
+            output_pos: 0,
+        };
+        assert_eq!(res, Err(illegal));
+        let res = decoder::validate(encoded, &Config::new_unchecked(0, 0));
+        assert_eq!(res, Err(DecodeError::InvalidConfig));
+    }
+
+    #[test]
+    fn decoded_len() {
+        let cfg = Config::new(8, 4).unwrap();
+        let mut dst1 = [0; 2300];
+        let mut dst2 = [0; 2000];
+        for repeat in [0, 160, 256] {
+            let src = Corpus::new(repeat).generate(2000, repeat);
+            let encoded = encoder::encode(&src, &mut dst1, &cfg).unwrap();
+            for len in [0, 1, 2, 100, encoded.len() - 1, encoded.len()] {
+                let truncated = &encoded[..len];
+                let decoded = decoder::decode(truncated, &mut dst2, &cfg).map(|d| d.len());
+                assert_eq!(decoder::decoded_len(truncated, &cfg), decoded);
+            }
+            assert_eq!(decoder::decoded_len(encoded, &cfg), Ok(src.len()));
+        }
+        // The zero window is allowed, as by decode()
+        assert_eq!(decoder::decoded_len(&[0x00, 0x00], &cfg), Ok(1));
+        let res = decoder::decoded_len(&[0x00], &Config::new_unchecked(4, 4));
+        assert_eq!(res, Err(DecodeError::InvalidConfig));
+    }
+
+    #[test]
+    fn len_results() {
+        let src = b"abcdefgh abcdefgh abcdefgh abcdefgh";
+        let cfg = Config::new(8, 4).unwrap();
+        let mut dst1 = [0; 100];
+        let mut dst2 = [0; 100];
+        let expected = encoder::encode(src, &mut dst2, &cfg).unwrap().to_vec();
+        let len = encoder::encode_len(src, &mut dst1, &cfg).unwrap();
+        assert_eq!(dst1[..len], expected);
+        let len = decoder::decode_len(&dst1[..len], &mut dst2, &cfg).unwrap();
+        assert_eq!(&dst2[..len], src);
+        let res = encoder::encode_len(src, &mut dst1[..4], &cfg);
+        assert_eq!(res, Err(EncodeError::OutputFull));
+        let res = decoder::decode_len(&expected, &mut dst2[..4], &cfg);
+        assert!(matches!(
+            res,
+            Err(DecodeError::OutputFull { written: 4, .. })
+        ));
+    }
+
+    #[test]
+    fn decode_limited() {
+        // A few bytes of runs expand to a kilobyte
+        let src = [7; 1000];
+        let cfg = Config::new(8, 4).unwrap();
+        let mut dst1 = [0; 200];
+        let mut dst2 = [0; 2000];
+        let encoded = encoder::encode(&src, &mut dst1, &cfg).unwrap();
+        assert!(encoded.len() < 200);
+        for limit in [0, 1, 999] {
+            let res = decoder::decode_limited(encoded, &mut dst2, limit, &cfg);
+            assert_eq!(res, Err(DecodeError::OutputLimitExceeded));
+        }
+        for limit in [1000, 1500, usize::MAX] {
+            let res = decoder::decode_limited(encoded, &mut dst2, limit, &cfg);
+            assert_eq!(res.unwrap(), src);
+        }
+        // The buffer being the smaller bound is not the limit's fault
+        let res = decoder::decode_limited(encoded, &mut dst2[..500], 800, &cfg);
+        assert!(matches!(res, Err(DecodeError::OutputFull { .. })));
+        let res = decoder::decode_limited(encoded, &mut dst2[..1000], 1000, &cfg);
+        assert_eq!(res.unwrap(), src);
+    }
+
+    #[test]
+    fn decode_in_place() {
+        // Text, then zeros compressing far better, then random bytes not
+        // compressing at all, which needs the most room
+        let mut mixed = Corpus::new(3).generate(1500, 200);
+        mixed.extend_from_slice(&[0; 500]);
+        mixed.extend(Corpus::new(4).generate(500, 0));
+        let inputs = [Corpus::new(1).generate(2000, 160), mixed];
+        let mut dst1 = [0; 3000];
+        let mut dst2 = [0; 3000];
+        for src in &inputs {
+            for (window, lookahead) in [(4, 3), (8, 4), (11, 4)] {
+                let cfg = Config::new(window, lookahead).unwrap();
+                for compat in [false, true] {
+                    let opts = EncodeOptions::new().with_compat(compat);
+                    let encoded =
+                        encoder::encode_with_options(src, &mut dst1, &cfg, &opts).unwrap();
+                    let needed = decoder::in_place_len(encoded, &cfg).unwrap();
+                    assert!(needed >= src.len() && needed <= src.len() + encoded.len());
+                    let mut buf = [0; 5000];
+                    buf[..encoded.len()].copy_from_slice(encoded);
+                    let decoded =
+                        decoder::decode_in_place(&mut buf[..needed], encoded.len(), &cfg).unwrap();
+                    assert_eq!(decoded, &src[..]);
+                    // One byte less would overwrite input not yet read,
+                    // which is found before the buffer is changed
+                    buf[..encoded.len()].copy_from_slice(encoded);
+                    let res = decoder::decode_in_place(&mut buf[..needed - 1], encoded.len(), &cfg);
+                    assert!(matches!(res, Err(DecodeError::OutputFull { .. })));
+                    assert_eq!(&buf[..encoded.len()], encoded);
+                }
+            }
+        }
+        let encoded = encoder::encode(&inputs[1], &mut dst1, &Config::default()).unwrap();
+        let margin = decoder::in_place_len(encoded, &Config::default()).unwrap() - 2500;
+        let even = encoder::encode(&inputs[0], &mut dst2, &Config::default()).unwrap();
+        let even_margin = decoder::in_place_len(even, &Config::default()).unwrap() - 2000;
+        assert!(
+            margin > 50 && even_margin < 10,
+            "{} {}",
+            margin,
+            even_margin
+        );
+        let mut buf = [0; 4];
+        let res = decoder::decode_in_place(&mut buf, 2, &Config::new_unchecked(0, 0));
+        assert_eq!(res, Err(DecodeError::InvalidConfig));
+        assert_eq!(
+            decoder::decode_in_place(&mut buf, 0, &Config::default()),
+            Ok(&[][..])
+        );
+    }
+
+    #[test]
+    fn error_positions() {
+        let src = b"The dictionary holds the first words. The dictionary words repeat.";
+        let dict = b"dictionary words";
+        let cfg = Config::new(8, 4).unwrap();
+        let mut dst1 = [0; 100];
+        let mut dst2 = [0; 100];
+        let encoded = encoder::encode_with_dict(src, &mut dst1, dict, &cfg).unwrap();
+        // Without the dictionary, the first backref points before the start
+        let opts = DecodeOptions::new().with_zero_window(false);
+        let err = decoder::decode_with_options(encoded, &mut dst2, &cfg, &opts).unwrap_err();
+        let DecodeError::IllegalBackref {
+            input_bit,
+            output_pos,
+        } = err
+        else {
+            panic!("Unexpected error {:?}", err);
+        };
+        assert_eq!(output_pos, 4);
+        assert_eq!(input_bit, 4 * 9);
+        assert_eq!(
+            decoder::validate_with_options(encoded, &cfg, &opts),
+            Err(err)
+        );
+        let mut window = [0; 256];
+        let mut dec = StreamDecoder::with_options(&mut window[..], &cfg, &opts);
+        let mut consumed = 0;
+        let res = loop {
+            assert!(consumed < encoded.len());
+            consumed += dec.sink(&encoded[consumed..]);
+            if let Err(err) = dec.poll(&mut dst2) {
+                break err;
+            }
+        };
+        assert_eq!(res, err);
+        // Running out of output is reported at the symbol that did not fit,
+        // counting the header of framed data
+        let encoded = encoder::encode(src, &mut dst1, &cfg).unwrap();
+        let err = decoder::decode(encoded, &mut dst2[..40], &cfg).unwrap_err();
+        let DecodeError::OutputFull { written, input_bit } = err else {
+            panic!("Unexpected error {:?}", err);
+        };
+        let mut truncated = [0; 100];
+        let decoded =
+            decoder::decode(&encoded[..input_bit.div_ceil(8)], &mut truncated, &cfg).unwrap();
+        assert_eq!(decoded, &src[..written]);
+        assert!(input_bit > 0 && written <= 40);
+        let framed = super::encode_framed(src, &mut dst1, &cfg).unwrap();
+        let res = super::decode_framed(framed, &mut dst2[..40]);
+        let full = DecodeError::OutputFull {
+            written,
+            input_bit: input_bit + 16,
+        };
+        assert_eq!(res, Err(full));
+    }
+
+    #[test]
+    fn decode_frames_into() {
+        let cfg = Config::new(8, 4).unwrap();
+        let frames: [&[u8]; 3] = [b"abcabcabcabc", b"", b"abcdabcdabcd"];
+        let mut encoded = [[0; 20]; 3];
+        let mut dst = [0; 30];
+        let mut end = 0;
+        for (frame, buf) in frames.iter().zip(encoded.iter_mut()) {
+            let encoded = encoder::encode(frame, buf, &cfg).unwrap();
+            end = decoder::decode_into(encoded, &mut dst, end, &cfg).unwrap();
+        }
+        assert_eq!(&dst[..end], b"abcabcabcabcabcdabcdabcd");
+        // A backref before the frame reads zeros, not the previous frame
+        let zeros = [0x00, 0x00];
+        assert_eq!(decoder::decode_into(&zeros, &mut dst, 3, &cfg), Ok(4));
+        assert_eq!(&dst[..4], b"abc\0");
+        let res = decoder::decode_into(b"", &mut dst, 31, &cfg);
+        let full = |written, input_bit| DecodeError::OutputFull { written, input_bit };
+        assert_eq!(res, Err(full(31, 0)));
+        let encoded = encoder::encode(frames[0], &mut encoded[0], &cfg).unwrap();
+        // Only the 3 literals fit, not the back-reference repeating them
+        let res = decoder::decode_into(encoded, &mut dst, 25, &cfg);
+        assert_eq!(res, Err(full(28, 27)));
+    }
+
+    #[test]
+    fn lookahead_10() {
+        // Backrefs of up to 1024 bytes, with counts split over two states.
+        // The expected output was produced by the C encoder
+        let mut src = [0; 1200];
+        for (b, t) in src.iter_mut().zip(b"hello world ".iter().cycle()) {
+            *b = *t;
+        }
+        let expected = hex_literal::hex!("b4596d96cb7c82ef6fb95b2c920005ffe00b28c0");
+        let cfg = Config::new(12, 10).unwrap();
+        let mut dst1 = [0; 100];
+        let mut dst2 = [0; 1201];
+        let encoded = encoder::encode(&src, &mut dst1, &cfg).unwrap();
+        assert_eq!(encoded, expected);
+        let decoded = decoder::decode(encoded, &mut dst2, &cfg).unwrap();
+        assert_eq!(decoded, src);
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn decode_owned() {
+        use std::vec;
+        // Compresses to a small fraction, so the output grows many times
+        let src = vec![7; 100_000];
+        let cfg = Config::default();
+        let mut dst = vec![0; 20_000];
+        let encoded = encoder::encode(&src, &mut dst, &cfg).unwrap();
+        assert_eq!(decoder::decode_owned(encoded, &cfg).unwrap(), src);
+        assert_eq!(decoder::decode_owned(&[], &cfg).unwrap(), b"");
+        let opts = Config::new_unchecked(0, 4);
+        assert_eq!(
+            decoder::decode_owned(encoded, &opts),
+            Err(DecodeError::InvalidConfig)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "heapless")]
+    fn heapless_round_trip() {
+        let src = b"abcabcabcabcabcabcabcabc";
+        let cfg = Config::default();
+        let encoded = encoder::encode_heapless::<16>(src, &cfg).unwrap();
+        let decoded = decoder::decode_heapless::<32>(&encoded, &cfg).unwrap();
+        assert_eq!(decoded, src);
+        let res = encoder::encode_heapless::<4>(src, &cfg);
+        assert_eq!(res, Err(EncodeError::OutputFull));
+        let res = decoder::decode_heapless::<16>(&encoded, &cfg);
+        assert!(matches!(res, Err(DecodeError::OutputFull { .. })));
+    }
+
+    #[test]
+    fn lookahead_exceeds_window() {
+        assert_eq!(
+            Config::new(8, 12).unwrap_err(),
+            ConfigError::LookaheadExceedsWindow
+        );
+        assert!(Config::new(8, 8).is_err());
+        assert!(Config::new(8, 7).is_ok());
+        assert!(Config::default().with_window(4).is_err());
+        assert!(Config::default().with_lookahead(11).is_err());
+    }
+
+    #[test]
+    fn config_builder() {
+        assert_eq!(Config::builder().build(), Ok(Config::DEFAULT));
+        // Growing the lookahead beyond the window first is fine
+        let cfg = Config::builder().lookahead(12).window(14).build();
+        assert_eq!(cfg, Config::new(14, 12));
+        assert!(Config::default().with_lookahead(12).is_err());
+        let cfg = Config::builder().window(5).lookahead(2).build();
+        assert_eq!(cfg, Config::new(5, 2));
+        let res = Config::builder().window(8).lookahead(9).build();
+        assert_eq!(res, Err(ConfigError::LookaheadExceedsWindow));
+        let res = Config::builder().window(17).build();
+        assert_eq!(res, Err(ConfigError::WindowTooLarge));
+        const CFG: Result<Config, ConfigError> = Config::builder().window(8).build();
+        assert_eq!(CFG, Config::new(8, 4));
+    }
+
+    #[test]
+    fn const_config() {
+        const CFG: Config = Config::new_unchecked(8, 4);
+        const WINDOW: u8 = CFG.window_sz2();
+        assert_eq!(WINDOW, 8);
+        assert!(Config::new(CFG.window_sz2(), CFG.lookahead_sz2()).is_ok());
+        assert_eq!(Config::default().window_sz2(), Config::DEFAULT.window_sz2());
+        assert_eq!(Config::DEFAULT.lookahead_sz2(), 4);
+    }
+
+    #[test]
+    fn decode_invalid_config() {
+        let src = [0xb0, 0x80, 0x3c];
+        let mut dst = [0; 20];
+        for (window, lookahead) in [(0, 0), (17, 4), (4, 4)] {
+            let cfg = Config::new_unchecked(window, lookahead);
+            let res = decoder::decode(&src, &mut dst, &cfg);
+            assert!(matches!(res, Err(DecodeError::InvalidConfig)));
+        }
+    }
+
+    #[test]
+    fn encode_invalid_config() {
+        let src = b"abcabc";
+        let mut dst = [0; 20];
+        for (window, lookahead) in [(0, 0), (17, 4), (4, 4)] {
+            let cfg = Config::new_unchecked(window, lookahead);
+            let res = encoder::encode(src, &mut dst, &cfg);
+            assert_eq!(res, Err(EncodeError::InvalidConfig));
+        }
+    }
+
+    #[test]
+    fn getters() {
+        let cfg = Config::new(13, 5).unwrap();
+        assert_eq!(cfg.window_sz2(), 13);
+        assert_eq!(cfg.lookahead_sz2(), 5);
+    }
+
+    #[test]
+    fn lookahead_range() {
+        assert_eq!(
+            Config::new(11, 0).unwrap_err(),
+            ConfigError::LookaheadTooSmall
+        );
+        assert_eq!(
+            Config::new(11, 17).unwrap_err(),
+            ConfigError::LookaheadTooLarge
+        );
+        assert_eq!(Config::new(17, 4).unwrap_err(), ConfigError::WindowTooLarge);
+        assert_eq!(Config::new(0, 4).unwrap_err(), ConfigError::WindowTooSmall);
+    }
+
+    #[test]
+    fn config_byte() {
+        let mut valid = 0;
+        for byte in 0..=255 {
+            match Config::from_byte(byte) {
+                Ok(cfg) => {
+                    assert_eq!(cfg.to_byte(), byte);
+                    valid += 1;
+                }
+                Err(err) => {
+                    let (window, lookahead) = ((byte >> 4) + 1, (byte & 0xf) + 1);
+                    assert_eq!(Config::new(window, lookahead), Err(err));
+                }
+            }
+        }
+        // Every window of 2 - 16 with each lookahead below it
+        assert_eq!(valid, (1..=15).sum::<i32>());
+        assert_eq!(Config::DEFAULT.to_byte(), 0xa3);
+        assert_eq!(Config::from_byte(0xa3), Ok(Config::DEFAULT));
+        assert_eq!(Config::from_byte(0x0f), Err(ConfigError::WindowTooSmall));
+        assert_eq!(Config::from_byte(0xff), Err(ConfigError::LookaheadTooLarge));
+        assert_eq!(
+            Config::from_byte(0x33),
+            Err(ConfigError::LookaheadExceedsWindow)
+        );
+    }
+
+    #[test]
+    fn range_constants() {
+        let windows = Config::MIN_WINDOW_SZ2..=Config::MAX_WINDOW_SZ2;
+        let lookaheads = Config::MIN_LOOKAHEAD_SZ2..=Config::MAX_LOOKAHEAD_SZ2;
+        // Every value in range is part of some valid configuration
+        for window in windows.clone() {
+            assert!(Config::new(window, 1).is_ok());
+        }
+        for lookahead in lookaheads.clone() {
+            assert!(Config::new(16, lookahead).is_ok());
+        }
+        for value in 0..=20 {
+            if !windows.contains(&value) {
+                assert!(Config::new(value, 1).is_err());
+            }
+            if !lookaheads.contains(&value) {
+                assert!(Config::new(16, value).is_err());
+            }
+        }
+        assert_eq!(Config::new(1, 1).unwrap_err(), ConfigError::WindowTooSmall);
+        assert_eq!(
+            Config::new(16, 16).unwrap_err(),
+            ConfigError::LookaheadTooLarge
+        );
+    }
+
+    #[test]
+    fn random_fuzz_backref_before_start() {
+        // Literal followed by a backref reaching 2 bytes before the start
+        let src = [0x90, 0x80, 0x11, 0x00];
+        let mut out = [0xff; 20];
+        let cfg: Config = Default::default();
+        let decoded = decoder::decode(&src, &mut out, &cfg).unwrap();
+        assert_eq!(decoded, [0x21, 0, 0, 0x21]);
+        let opts = DecodeOptions::new().with_zero_window(false);
+        let res = decoder::decode_with_options(&src, &mut out, &cfg, &opts);
+        let illegal = DecodeError::IllegalBackref {
+            input_bit: 9,
+            output_pos: 1,
+        };
+        assert_eq!(res, Err(illegal));
+    }
+
+    #[test]
+    fn clib_window_8() {
+        // Reference output of the C encoder with a window of 8, where the
+        // decoder skips the index msb state. After the literals "abc" comes
+        // a backref with index bits 00000010 for distance 3, and count bits
+        // 0101 for 6 bytes, so the C index is also the distance minus one
+        let src = b"abcabcabcXYZabcabcXYZ";
+        let expected = hex_literal::hex!("b0d8ac6025ac566b4088");
+        let cfg = Config::new(8, 4).unwrap();
+        let mut dst1 = [0; 40];
+        let mut dst2 = [0; 40];
+        let opts = EncodeOptions::new().with_compat(true);
+        let encoded = encoder::encode_with_options(src, &mut dst1, &cfg, &opts).unwrap();
+        assert_eq!(encoded, expected);
+        let decoded = decoder::decode(&expected, &mut dst2, &cfg).unwrap();
+        assert_eq!(decoded, src);
+    }
+
+    #[test]
+    fn small_windows() {
+        // Repeats at exactly the window size exercise the largest index
+        let src = Corpus::new(1).generate(600, 0);
+        let mut dst1 = [0; 700];
+        let mut dst2 = [0; 601];
+        for window in 5..=8 {
+            let wsize = 1 << window;
+            let mut data = src.clone();
+            data.copy_within(..wsize, wsize);
+            for lookahead in 1..window {
+                let cfg = Config::new(window, lookahead).unwrap();
+                let (encoded, stats) = encoder::encode_with_stats(&data, &mut dst1, &cfg).unwrap();
+                assert!(stats.backrefs > 0);
+                let decoded = decoder::decode(encoded, &mut dst2, &cfg).unwrap();
+                assert_eq!(decoded, data);
+            }
+        }
+    }
+
+    #[test]
+    fn clib_compatibility_zero_window() {
+        // The C encoder output refers to the zero filled window
+        let src = hex_literal::hex!("90D4B2B549A408057C003E0100C9811B7CA05F1817C002DA5F04025F0005");
+        let cfg = Config::new(11, 4).unwrap();
+        let opts = DecodeOptions::new().with_zero_window(false);
+        let mut dst = [0; 100];
+        let res = decoder::decode_with_options(&src, &mut dst, &cfg, &opts);
+        assert!(matches!(res, Err(DecodeError::IllegalBackref { .. })));
+    }
+
+    #[test]
+    fn block_wise_history() {
+        let mut src = [0; 3000];
+        for (i, b) in src.iter_mut().enumerate() {
+            *b = (i * 7 % 23) as u8 ^ (i / 500) as u8;
+        }
+        let cfg = Config::new(8, 4).unwrap();
+        let history = 1 << 8;
+        let mut compressed = [0; 1000];
+        let mut window = [0; 1300];
+        let mut decoded = [0; 3000];
+        let mut pos = 0;
+        for block in src.chunks(1000) {
+            // Encode each block with the preceding window as history
+            let start = pos - history.min(pos);
+            let frame = encoder::encode_with_history(
+                &src[start..pos + block.len()],
+                &mut compressed,
+                pos - start,
+                &cfg,
+            )
+            .unwrap();
+            window[..pos - start].copy_from_slice(&decoded[start..pos]);
+            let out = decoder::decode_with_history(frame, &mut window, pos - start, &cfg).unwrap();
+            decoded[pos..pos + out.len()].copy_from_slice(out);
+            pos += out.len();
+        }
+        assert_eq!(decoded, src);
+    }
+
+    #[test]
+    fn backref_max_count() {
+        // Literal 'a' followed by a backref at distance 1 with an all ones count
+        let src = [0xb0, 0x80, 0x3c];
+        let cfg = Config::new(8, 4).unwrap();
+        let mut dst = [0; 20];
+        let out = decoder::decode(&src, &mut dst, &cfg).unwrap();
+        assert_eq!(out, [b'a'; 17]);
+        // The count field can not hold more than the lookahead, also when
+        // it is read in two parts
+        let mut dst = [0; 5000];
+        let mut window = [0; 1 << 13];
+        for lookahead in [8, 9, 12] {
+            let cfg = Config::new(13, lookahead).unwrap();
+            // Literal 'a', then a backref at distance 1 with an all ones count
+            let mut src = [0; 6];
+            let bits = (0x161u64 << (14 + lookahead) | ((1 << lookahead) - 1)) << (25 - lookahead);
+            src.copy_from_slice(&bits.to_be_bytes()[2..]);
+            let out = decoder::decode(&src, &mut dst, &cfg).unwrap();
+            assert_eq!(out.len(), 1 + (1 << lookahead));
+            assert!(out.iter().all(|&b| b == b'a'));
+            let mut dec = StreamDecoder::new(&mut window[..], &cfg);
+            let (mut consumed, mut total) = (0, 0);
+            while consumed < src.len() {
+                consumed += dec.sink(&src[consumed..]);
+                total += dec.poll(&mut dst[total..]).unwrap();
+            }
+            assert_eq!(total, 1 + (1 << lookahead));
+        }
+    }
+
+    #[test]
+    fn preset_dictionary() {
+        let dict = b"{\"sensor\":\"temperature\",\"unit\":\"celsius\",\"value\":";
+        let src = b"{\"sensor\":\"temperature\",\"unit\":\"celsius\",\"value\":21.5}";
+        let mut dst1 = [0; 100];
+        let mut dst2 = [0; 100];
+        let mut dst3 = [0; 100];
+        for cfg in [Config::new(8, 4).unwrap(), Config::new(4, 3).unwrap()] {
+            let plain = encoder::encode(src, &mut dst1, &cfg).unwrap();
+            let encoded = encoder::encode_with_dict(src, &mut dst2, dict, &cfg).unwrap();
+            assert!(encoded.len() < plain.len());
+            let decoded = decoder::decode_with_dict(encoded, &mut dst3, dict, &cfg).unwrap();
+            assert_eq!(decoded, src);
+        }
+        // Without the dictionary they point before the start of the output
+        let cfg = Config::new(8, 4).unwrap();
+        let encoded = encoder::encode_with_dict(src, &mut dst2, dict, &cfg).unwrap();
+        let opts = DecodeOptions::new().with_zero_window(false);
+        let mut dec = HeatshrinkDecoder::with_options(encoded, &mut dst3, &cfg, &opts);
+        assert!(matches!(
+            dec.decode(),
+            Err(DecodeError::IllegalBackref { .. })
+        ));
+    }
+
+    #[test]
+    fn max_window_distance() {
+        // The only match for the final bytes is 65536 bytes back
+        let mut src = [0; (1 << 16) + 4];
+        src[..4].copy_from_slice(&[1, 2, 3, 4]);
+        src[1 << 16..].copy_from_slice(&[1, 2, 3, 4]);
+        let cfg = Config::new(16, 4).unwrap();
+        let mut compressed = [0; 10];
+        let frame = encoder::encode_with_history(&src, &mut compressed, 1 << 16, &cfg).unwrap();
+        // Tag bit, 16 index bits of all ones and 4 count bits for 4 bytes
+        assert_eq!(frame, [0x7f, 0xff, 0x98]);
+        let mut dst = [0; (1 << 16) + 16];
+        dst[..1 << 16].copy_from_slice(&src[..1 << 16]);
+        let decoded = decoder::decode_with_history(frame, &mut dst, 1 << 16, &cfg).unwrap();
+        assert_eq!(decoded, [1, 2, 3, 4]);
+    }
+
+    // Random data, and data with a small alphabet which produces many backrefs
+    fn any_input() -> impl proptest::strategy::Strategy<Value = std::vec::Vec<u8>> {
+        use proptest::prelude::*;
+        prop_oneof![
+            proptest::collection::vec(any::<u8>(), 0..600),
+            proptest::collection::vec(0u8..4, 0..600),
+        ]
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn round_trip_any_config(
+            (window, lookahead) in (2u8..=16).prop_flat_map(|w| (Just(w), 1..w)),
+            src in any_input(),
+        ) {
+            use proptest::prelude::*;
+            use std::vec;
+            let cfg = Config::new(window, lookahead).unwrap();
+            // Worst case of a 9 bit literal per byte
+            let mut dst1 = vec![0; src.len() * 9 / 8 + 1];
+            let mut dst2 = vec![0; src.len()];
+            let encoded = encoder::encode(&src, &mut dst1, &cfg).unwrap();
+            let decoded = decoder::decode(encoded, &mut dst2, &cfg).unwrap();
+            prop_assert_eq!(decoded, &src[..]);
+        }
+    }
 }
@@ -0,0 +1,198 @@
+use super::{
+    decode_with_options, encode, validate_with_options, Config, DecodeError, DecodeOptions,
+    EncodeError,
+};
+
+// Marker starting every segment, which the decoder searches for to resync
+const SYNC_MARKER: [u8; 4] = *b"HSYN";
+// Marker, compressed length and decompressed length, as little endian u16
+const HEADER_SIZE: usize = 8;
+/// Largest interval `encode_resync()` accepts, so that the lengths of a
+/// segment fit in its header
+pub const MAX_RESYNC_INTERVAL: usize = 32768;
+
+/// Compression call inserting resync points every `interval` bytes of
+/// input, so that corrupted data only loses the segments it hits, rather
+/// than the rest of the stream. Each segment is compressed on its own,
+/// byte aligned, and starts with an 8 byte header holding a marker and
+/// the lengths, which `decode_resync()` searches for after damage.
+///
+/// This costs the header and up to a byte of padding per segment, plus
+/// the matches that can not reach into the previous segment, which is most
+/// of the cost. On text with the default window, the output grows by about
+/// 45% for 1 kB segments, 13% for 4 kB, and 2 - 4% for 16 kB
+///
+/// # Panics
+///
+/// Panics if `interval` is 0 or above `MAX_RESYNC_INTERVAL`
+pub fn encode_resync<'a>(
+    input: &[u8],
+    output: &'a mut [u8],
+    interval: usize,
+    cfg: &Config,
+) -> Result<&'a [u8], EncodeError> {
+    assert!(interval > 0 && interval <= MAX_RESYNC_INTERVAL);
+    let mut len = 0;
+    for segment in input.chunks(interval) {
+        let rest = &mut output[len..];
+        if rest.len() < HEADER_SIZE {
+            return Err(EncodeError::OutputFull);
+        }
+        let (header, data) = rest.split_at_mut(HEADER_SIZE);
+        let compressed = encode(segment, data, cfg)?.len();
+        header[..4].copy_from_slice(&SYNC_MARKER);
+        header[4..6].copy_from_slice(&(compressed as u16).to_le_bytes());
+        header[6..].copy_from_slice(&(segment.len() as u16).to_le_bytes());
+        len += HEADER_SIZE + compressed;
+    }
+    Ok(&output[..len])
+}
+
+/// Decompression call for data produced by `encode_resync()`, skipping
+/// damaged segments. Returns the data of all intact segments, along with
+/// the number of input bytes skipped, which is 0 for an undamaged stream.
+/// A segment counts as damaged when it does not decode to the length in
+/// its header, so corruption that still decodes to the right length goes
+/// unnoticed, like a flipped bit in a literal. An intact segment that
+/// does not fit in the output results in `DecodeError::OutputFull`
+pub fn decode_resync<'a>(
+    input: &[u8],
+    output: &'a mut [u8],
+    cfg: &Config,
+) -> Result<(&'a [u8], usize), DecodeError> {
+    let strict = DecodeOptions::new().with_strict(true);
+    let mut pos = 0;
+    let mut len = 0;
+    let mut skipped = 0;
+    while pos < input.len() {
+        if let Some((data, size)) = segment(&input[pos..]) {
+            let res = match output.get_mut(len..len + size) {
+                Some(dst) => decode_with_options(data, dst, cfg, &strict).map(|d| d.len()),
+                // Only an intact segment counts as not fitting, as a
+                // damaged header may hold any size
+                None => match validate_with_options(data, cfg, &strict) {
+                    Ok(decoded) if decoded == size => {
+                        return Err(DecodeError::OutputFull {
+                            written: len,
+                            input_bit: pos * 8,
+                        })
+                    }
+                    res => res,
+                },
+            };
+            match res {
+                Ok(decoded) if decoded == size => {
+                    pos += HEADER_SIZE + data.len();
+                    len += size;
+                    continue;
+                }
+                Err(DecodeError::InvalidConfig) => return Err(DecodeError::InvalidConfig),
+                _ => {}
+            }
+        }
+        // Search for the next marker
+        let next = input[pos + 1..]
+            .windows(SYNC_MARKER.len())
+            .position(|window| window == SYNC_MARKER)
+            .map_or(input.len(), |offset| pos + 1 + offset);
+        skipped += next - pos;
+        pos = next;
+    }
+    Ok((&output[..len], skipped))
+}
+
+// Splits off the compressed data and decompressed length of the segment
+// starting `input`, if its header is intact
+fn segment(input: &[u8]) -> Option<(&[u8], usize)> {
+    let header = input.get(..HEADER_SIZE)?;
+    if header[..4] != SYNC_MARKER {
+        return None;
+    }
+    let compressed = u16::from_le_bytes([header[4], header[5]]) as usize;
+    let size = u16::from_le_bytes([header[6], header[7]]) as usize;
+    let data = input.get(HEADER_SIZE..HEADER_SIZE + compressed)?;
+    Some((data, size))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode_resync, encode_resync};
+    use crate::{Config, DecodeError};
+
+    const TEXT: &[u8] = b"It is a truth universally acknowledged, that a single man in \
+        possession of a good fortune, must be in want of a wife. ";
+
+    fn sample() -> [u8; 2000] {
+        let mut src = [0; 2000];
+        for (b, t) in src.iter_mut().zip(TEXT.iter().cycle()) {
+            *b = *t;
+        }
+        src
+    }
+
+    #[test]
+    fn round_trip() {
+        let src = sample();
+        let cfg = Config::default();
+        let mut dst1 = [0; 5000];
+        let mut dst2 = [0; 2000];
+        for interval in [20, 100, 500, 2000, 32768] {
+            let encoded = encode_resync(&src, &mut dst1, interval, &cfg).unwrap();
+            let (decoded, skipped) = decode_resync(encoded, &mut dst2, &cfg).unwrap();
+            assert_eq!((decoded, skipped), (&src[..], 0));
+        }
+        let encoded = encode_resync(&[], &mut [], 100, &cfg).unwrap();
+        assert_eq!(decode_resync(encoded, &mut [], &cfg).unwrap(), (&[][..], 0));
+    }
+
+    #[test]
+    fn damage() {
+        let src = sample();
+        let cfg = Config::default();
+        let mut dst1 = [0; 3000];
+        let mut dst2 = [0; 2000];
+        let len = encode_resync(&src, &mut dst1, 500, &cfg).unwrap().len();
+        // Breaking the second segment header loses only that segment
+        let mut damaged = dst1;
+        let second = 8 + u16::from_le_bytes([damaged[4], damaged[5]]) as usize;
+        damaged[second + 1] ^= 0x40;
+        let (decoded, skipped) = decode_resync(&damaged[..len], &mut dst2, &cfg).unwrap();
+        assert_eq!(decoded.len(), 1500);
+        assert_eq!(decoded[..500], src[..500]);
+        assert_eq!(decoded[500..], src[1000..]);
+        assert!(skipped > 0);
+        // As does a length that no longer matches the data, larger or smaller
+        for flip in [0x01, 0x04] {
+            let mut damaged = dst1;
+            damaged[second + 6] ^= flip;
+            let (decoded, _) = decode_resync(&damaged[..len], &mut dst2, &cfg).unwrap();
+            assert_eq!(decoded.len(), 1500);
+        }
+        // Or one too large for the output
+        let mut damaged = dst1;
+        damaged[7] ^= 0x40;
+        let (decoded, skipped) = decode_resync(&damaged[..len], &mut dst2, &cfg).unwrap();
+        assert_eq!(decoded, &src[500..]);
+        assert!(skipped > 0);
+        // Leading garbage and a cut off end are skipped
+        let mut shifted = [0xee; 3003];
+        shifted[3..3 + len - 3].copy_from_slice(&dst1[..len - 3]);
+        let (decoded, skipped) = decode_resync(&shifted[..len], &mut dst2, &cfg).unwrap();
+        assert_eq!(decoded, &src[..1500]);
+        assert!(skipped > 3);
+    }
+
+    #[test]
+    fn output_full() {
+        let src = sample();
+        let cfg = Config::default();
+        let mut dst1 = [0; 3000];
+        let mut dst2 = [0; 1200];
+        let encoded = encode_resync(&src, &mut dst1, 500, &cfg).unwrap();
+        let res = decode_resync(encoded, &mut dst2, &cfg);
+        assert!(matches!(
+            res,
+            Err(DecodeError::OutputFull { written: 1000, .. })
+        ));
+    }
+}
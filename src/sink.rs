@@ -0,0 +1,518 @@
+use super::{Config, DecodeError, EncodeError, HeatshrinkEncoder, StreamDecoder};
+use core::convert::Infallible;
+
+/// Destination for bytes, like a UART, a log or a flash page writer, that
+/// `encode_to_sink()` and `decode_to_sink()` write to as the data is coded
+pub trait ByteSink {
+    /// Error reported by the sink
+    type Error;
+
+    /// Writes all of `bytes`
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), Self::Error>;
+}
+
+/// Source of compressed bytes, like a flash reader, that
+/// `decode_from_source()` reads from as the data is decoded
+pub trait ByteSource {
+    /// Error reported by the source
+    type Error;
+
+    /// Reads up to `buf.len()` bytes, returning the number of bytes read,
+    /// which is 0 at the end of the data
+    fn read_bytes(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error>;
+}
+
+/// Error of a slice used as a `ByteSink`, which has no room left
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BufferFull;
+
+impl core::fmt::Display for BufferFull {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("Buffer is full")
+    }
+}
+
+impl core::error::Error for BufferFull {}
+
+/// Writes to the start of the slice, and moves it past the bytes written,
+/// like `std::io::Write` does. Writes that do not fit write nothing
+impl ByteSink for &mut [u8] {
+    type Error = BufferFull;
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), BufferFull> {
+        if bytes.len() > self.len() {
+            return Err(BufferFull);
+        }
+        let (head, tail) = core::mem::take(self).split_at_mut(bytes.len());
+        head.copy_from_slice(bytes);
+        *self = tail;
+        Ok(())
+    }
+}
+
+/// Reads from the start of the slice, and moves it past the bytes read
+impl ByteSource for &[u8] {
+    type Error = Infallible;
+
+    fn read_bytes(&mut self, buf: &mut [u8]) -> Result<usize, Infallible> {
+        let n = buf.len().min(self.len());
+        let (head, tail) = self.split_at(n);
+        buf[..n].copy_from_slice(head);
+        *self = tail;
+        Ok(n)
+    }
+}
+
+/// Errors returned when coding to a sink, or from a source
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SinkError<E, R = Infallible> {
+    /// The sink failed
+    Sink(E),
+    /// The compressed data was corrupt
+    Decode(DecodeError),
+    /// Compressing failed for another reason than the sink
+    Encode(EncodeError),
+    /// The source failed
+    Source(R),
+}
+
+impl<E: core::fmt::Display, R: core::fmt::Display> core::fmt::Display for SinkError<E, R> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            SinkError::Sink(err) => err.fmt(f),
+            SinkError::Decode(err) => err.fmt(f),
+            SinkError::Encode(err) => err.fmt(f),
+            SinkError::Source(err) => err.fmt(f),
+        }
+    }
+}
+
+impl<E, R> core::error::Error for SinkError<E, R>
+where
+    E: core::fmt::Debug + core::fmt::Display,
+    R: core::fmt::Debug + core::fmt::Display,
+{
+}
+
+// Size of the chunks handed to the sink, and read from the source
+const CHUNK_SIZE: usize = 64;
+
+/// Compresses `input`, writing the compressed data to `sink` a byte at a
+/// time as it is produced. Returns the number of bytes written
+pub fn encode_to_sink<S: ByteSink>(
+    input: &[u8],
+    sink: &mut S,
+    cfg: &Config,
+) -> Result<usize, SinkError<S::Error>> {
+    let mut encoder = HeatshrinkEncoder::new(input, &mut [], cfg);
+    Ok(encoder.run_into(sink, None)?.output_len)
+}
+
+/// Decompresses `input`, writing the decompressed data to `sink` in small
+/// chunks as it is produced, so that no buffer for all of it is needed.
+/// Back-references are resolved against `window`, which must be at least
+/// `1 << window_sz2` bytes. Returns the number of bytes written
+///
+/// # Panics
+///
+/// Panics if `window` is shorter than `1 << window_sz2` bytes
+pub fn decode_to_sink<S: ByteSink>(
+    input: &[u8],
+    window: &mut [u8],
+    sink: &mut S,
+    cfg: &Config,
+) -> Result<usize, SinkError<S::Error>> {
+    decode_from_source(&mut { input }, window, sink, cfg)
+}
+
+/// Decompresses the data read from `source` like `decode_to_sink()`,
+/// reading it in small chunks, so that neither the compressed nor the
+/// decompressed data needs a buffer for all of it
+///
+/// # Panics
+///
+/// Panics if `window` is shorter than `1 << window_sz2` bytes
+pub fn decode_from_source<R: ByteSource, S: ByteSink>(
+    source: &mut R,
+    window: &mut [u8],
+    sink: &mut S,
+    cfg: &Config,
+) -> Result<usize, SinkError<S::Error, R::Error>> {
+    let mut decoder = StreamDecoder::new(window, cfg);
+    let mut chunk = [0; CHUNK_SIZE];
+    let mut input = [0; CHUNK_SIZE];
+    let mut start = 0;
+    let mut end = 0;
+    loop {
+        let n = decoder.poll(&mut chunk).map_err(SinkError::Decode)?;
+        if n == 0 {
+            if start == end {
+                start = 0;
+                end = source.read_bytes(&mut input).map_err(SinkError::Source)?;
+                if end == 0 {
+                    return Ok(decoder.output_produced());
+                }
+            }
+            start += decoder.sink(&input[start..end]);
+        }
+        sink.write_bytes(&chunk[..n]).map_err(SinkError::Sink)?;
+    }
+}
+
+// Hands each byte to a callback
+struct CallbackSink<F>(F);
+
+impl<E, F: FnMut(u8) -> Result<(), E>> ByteSink for CallbackSink<F> {
+    type Error = E;
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), E> {
+        bytes.iter().try_for_each(|byte| (self.0)(*byte))
+    }
+}
+
+/// Compresses `input` like `encode_to_sink()`, handing each compressed
+/// byte to `emit` as it is produced, like for feeding a FIFO or DMA
+/// without an output buffer. An error returned by `emit` stops the
+/// compression, and is returned as `SinkError::Sink`
+pub fn encode_with_callback<E>(
+    input: &[u8],
+    cfg: &Config,
+    emit: impl FnMut(u8) -> Result<(), E>,
+) -> Result<usize, SinkError<E>> {
+    encode_to_sink(input, &mut CallbackSink(emit), cfg)
+}
+
+/// Decompresses `input` like `decode_to_sink()`, handing each decompressed
+/// byte to `emit`. Back-references are resolved against `window`, which
+/// must be at least `1 << window_sz2` bytes, and the bytes are decoded in
+/// chunks of 64 before being handed over. An error returned by `emit`
+/// stops the decompression, and is returned as `SinkError::Sink`
+///
+/// # Panics
+///
+/// Panics if `window` is shorter than `1 << window_sz2` bytes
+pub fn decode_with_callback<E>(
+    input: &[u8],
+    window: &mut [u8],
+    cfg: &Config,
+    emit: impl FnMut(u8) -> Result<(), E>,
+) -> Result<usize, SinkError<E>> {
+    decode_to_sink(input, window, &mut CallbackSink(emit), cfg)
+}
+
+// Writes bytes as text, holding back sequences split between chunks
+struct Utf8Sink<'a, W> {
+    inner: &'a mut W,
+    partial: [u8; 4],
+    partial_len: usize,
+}
+
+impl<W: core::fmt::Write> Utf8Sink<'_, W> {
+    fn write_valid(&mut self, mut bytes: &[u8]) -> core::fmt::Result {
+        loop {
+            match core::str::from_utf8(bytes) {
+                Ok(text) => return self.inner.write_str(text),
+                Err(err) => {
+                    let (valid, rest) = bytes.split_at(err.valid_up_to());
+                    // Checked to be valid just now
+                    self.inner.write_str(core::str::from_utf8(valid).unwrap())?;
+                    match err.error_len() {
+                        Some(len) => {
+                            self.inner.write_char(char::REPLACEMENT_CHARACTER)?;
+                            bytes = &rest[len..];
+                        }
+                        // Incomplete sequence at the end, completed by the next chunk
+                        None => {
+                            self.partial[..rest.len()].copy_from_slice(rest);
+                            self.partial_len = rest.len();
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<W: core::fmt::Write> ByteSink for Utf8Sink<'_, W> {
+    type Error = core::fmt::Error;
+
+    fn write_bytes(&mut self, mut bytes: &[u8]) -> core::fmt::Result {
+        // Complete a sequence left over from the previous chunk
+        while self.partial_len > 0 && !bytes.is_empty() {
+            self.partial[self.partial_len] = bytes[0];
+            self.partial_len += 1;
+            bytes = &bytes[1..];
+            let partial = self.partial;
+            let len = self.partial_len;
+            self.partial_len = 0;
+            self.write_valid(&partial[..len])?;
+        }
+        self.write_valid(bytes)
+    }
+}
+
+/// Decompresses `input` like `decode_to_sink()`, writing the decompressed
+/// data to a `core::fmt::Write`, like a serial console. This is intended for
+/// text, as `fmt::Write` only accepts UTF-8: invalid UTF-8 is written as
+/// U+FFFD replacement characters, while characters split between chunks
+/// are written whole
+///
+/// # Panics
+///
+/// Panics if `window` is shorter than `1 << window_sz2` bytes
+pub fn decode_to_fmt<W: core::fmt::Write>(
+    input: &[u8],
+    window: &mut [u8],
+    sink: &mut W,
+    cfg: &Config,
+) -> Result<usize, SinkError<core::fmt::Error>> {
+    let mut utf8 = Utf8Sink {
+        inner: sink,
+        partial: [0; 4],
+        partial_len: 0,
+    };
+    let len = decode_to_sink(input, window, &mut utf8, cfg)?;
+    if utf8.partial_len > 0 {
+        let res = utf8.inner.write_char(char::REPLACEMENT_CHARACTER);
+        res.map_err(SinkError::Sink)?;
+    }
+    Ok(len)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        decode_from_source, decode_to_fmt, decode_to_sink, decode_with_callback, encode_to_sink,
+        encode_with_callback, BufferFull, ByteSink, ByteSource, SinkError,
+    };
+    use crate::{decode, encode, Config, EncodeError};
+
+    struct Collect {
+        data: [u8; 256],
+        len: usize,
+    }
+
+    impl ByteSink for Collect {
+        type Error = ();
+
+        fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), ()> {
+            let end = self.len + bytes.len();
+            self.data
+                .get_mut(self.len..end)
+                .ok_or(())?
+                .copy_from_slice(bytes);
+            self.len = end;
+            Ok(())
+        }
+    }
+
+    impl core::fmt::Write for Collect {
+        fn write_str(&mut self, text: &str) -> core::fmt::Result {
+            self.write_bytes(text.as_bytes())
+                .map_err(|_| core::fmt::Error)
+        }
+    }
+
+    #[test]
+    fn callbacks() {
+        let src: [u8; 200] = core::array::from_fn(|i| (i * i % 7) as u8);
+        let cfg = Config::new(8, 4).unwrap();
+        let mut compressed = [0; 300];
+        let expected = encode(&src, &mut compressed, &cfg).unwrap();
+        let mut fifo = Collect {
+            data: [0; 256],
+            len: 0,
+        };
+        let res = encode_with_callback(&src, &cfg, |byte| fifo.write_bytes(&[byte]));
+        assert_eq!(res, Ok(expected.len()));
+        assert_eq!(fifo.data[..fifo.len], *expected);
+        let mut window = [0; 256];
+        let mut decoded = Collect {
+            data: [0; 256],
+            len: 0,
+        };
+        let res = decode_with_callback(expected, &mut window, &cfg, |byte| {
+            decoded.write_bytes(&[byte])
+        });
+        assert_eq!(res, Ok(200));
+        assert_eq!(decoded.data[..decoded.len], src);
+        // The first error stops the coding
+        let mut budget = 10;
+        let mut count = |_| {
+            budget -= 1;
+            if budget == 0 {
+                Err("full")
+            } else {
+                Ok(())
+            }
+        };
+        let res = decode_with_callback(expected, &mut window, &cfg, &mut count);
+        assert_eq!(res, Err(SinkError::Sink("full")));
+        assert_eq!(budget, 0);
+        let res = encode_with_callback(&src, &cfg, |_| Err(()));
+        assert_eq!(res, Err(SinkError::Sink(())));
+    }
+
+    #[test]
+    fn bytes() {
+        let src: [u8; 200] = core::array::from_fn(|i| (i * i % 7) as u8);
+        let cfg = Config::new(8, 4).unwrap();
+        let mut compressed = [0; 300];
+        let compressed = encode(&src, &mut compressed, &cfg).unwrap();
+        let mut window = [0; 256];
+        let mut sink = Collect {
+            data: [0; 256],
+            len: 0,
+        };
+        assert_eq!(
+            decode_to_sink(compressed, &mut window, &mut sink, &cfg),
+            Ok(200)
+        );
+        assert_eq!(sink.data[..sink.len], src);
+        let mut small = Collect {
+            data: [0; 256],
+            len: 100,
+        };
+        let res = decode_to_sink(compressed, &mut window, &mut small, &cfg);
+        assert_eq!(res, Err(SinkError::Sink(())));
+    }
+
+    #[test]
+    fn text() {
+        // Multi-byte characters straddle the 64 byte chunks
+        let mut src = [0; 180];
+        for (b, t) in src.iter_mut().zip("zürich ¿qué? €10 ".bytes().cycle()) {
+            *b = t;
+        }
+        let cfg = Config::new(8, 4).unwrap();
+        let mut compressed = [0; 300];
+        let compressed = encode(&src, &mut compressed, &cfg).unwrap();
+        let mut window = [0; 256];
+        let mut sink = Collect {
+            data: [0; 256],
+            len: 0,
+        };
+        assert_eq!(
+            decode_to_fmt(compressed, &mut window, &mut sink, &cfg),
+            Ok(180)
+        );
+        assert_eq!(sink.data[..sink.len], src);
+        // Invalid UTF-8, and a character cut short at the end
+        let mut buf = [0; 20];
+        let compressed = encode(b"a\xffb\xe2\x82", &mut buf, &cfg).unwrap();
+        let mut sink = Collect {
+            data: [0; 256],
+            len: 0,
+        };
+        assert_eq!(
+            decode_to_fmt(compressed, &mut window, &mut sink, &cfg),
+            Ok(5)
+        );
+        assert_eq!(sink.data[..sink.len], *"a\u{fffd}b\u{fffd}".as_bytes());
+    }
+
+    // Flash writer committing whole 32 byte pages
+    struct Pages {
+        flash: [u8; 256],
+        page: [u8; 32],
+        fill: usize,
+        committed: usize,
+    }
+
+    impl ByteSink for Pages {
+        type Error = ();
+
+        fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), ()> {
+            for b in bytes {
+                self.page[self.fill] = *b;
+                self.fill += 1;
+                if self.fill == self.page.len() {
+                    let flash = self.flash.get_mut(self.committed..self.committed + 32);
+                    flash.ok_or(())?.copy_from_slice(&self.page);
+                    self.committed += 32;
+                    self.fill = 0;
+                }
+            }
+            Ok(())
+        }
+    }
+
+    // Source handing out 3 bytes per read, failing past `fail_at`
+    struct Trickle<'a> {
+        data: &'a [u8],
+        fail_at: usize,
+    }
+
+    impl ByteSource for Trickle<'_> {
+        type Error = &'static str;
+
+        fn read_bytes(&mut self, buf: &mut [u8]) -> Result<usize, &'static str> {
+            if self.fail_at < 3 {
+                return Err("read failed");
+            }
+            self.fail_at -= 3;
+            let mut head = &self.data[..self.data.len().min(3)];
+            let n = head.read_bytes(buf).unwrap();
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn encode_sinks() {
+        let src: [u8; 300] = core::array::from_fn(|i| (i * i % 11) as u8);
+        let cfg = Config::new(8, 4).unwrap();
+        let mut expected = [0; 300];
+        let expected = encode(&src, &mut expected, &cfg).unwrap();
+        // A slice is filled from the front, and the rest of it is left over
+        let mut buf = [0; 300];
+        let mut rest = &mut buf[..];
+        assert_eq!(encode_to_sink(&src, &mut rest, &cfg), Ok(expected.len()));
+        assert_eq!(rest.len(), 300 - expected.len());
+        assert_eq!(&buf[..expected.len()], expected);
+        let mut small = [0; 10];
+        let res = encode_to_sink(&src, &mut &mut small[..], &cfg);
+        assert_eq!(res, Err(SinkError::Sink(BufferFull)));
+        let res = encode_to_sink(&src, &mut &mut buf[..], &Config::new_unchecked(8, 8));
+        assert_eq!(res, Err(SinkError::Encode(EncodeError::InvalidConfig)));
+        // Pages are committed as they fill up
+        let mut pages = Pages {
+            flash: [0; 256],
+            page: [0; 32],
+            fill: 0,
+            committed: 0,
+        };
+        assert_eq!(encode_to_sink(&src, &mut pages, &cfg), Ok(expected.len()));
+        assert_eq!(pages.committed, expected.len() / 32 * 32);
+        assert_eq!(pages.flash[..pages.committed], expected[..pages.committed]);
+        let mut dst = [0; 300];
+        assert_eq!(decode(expected, &mut dst, &cfg).unwrap(), src);
+    }
+
+    #[test]
+    fn sources() {
+        let src: [u8; 200] = core::array::from_fn(|i| (i * i % 7) as u8);
+        let cfg = Config::new(8, 4).unwrap();
+        let mut compressed = [0; 300];
+        let compressed = encode(&src, &mut compressed, &cfg).unwrap();
+        let mut window = [0; 256];
+        let mut dst = [0; 200];
+        let mut source = Trickle {
+            data: compressed,
+            fail_at: usize::MAX,
+        };
+        let res = decode_from_source(&mut source, &mut window, &mut &mut dst[..], &cfg);
+        assert_eq!(res, Ok(200));
+        assert_eq!(dst, src);
+        let mut source = Trickle {
+            data: compressed,
+            fail_at: 6,
+        };
+        let res = decode_from_source(&mut source, &mut window, &mut &mut dst[..], &cfg);
+        assert_eq!(res, Err(SinkError::Source("read failed")));
+        let mut small = [0; 100];
+        let res = decode_to_sink(compressed, &mut window, &mut &mut small[..], &cfg);
+        assert_eq!(res, Err(SinkError::Sink(BufferFull)));
+    }
+}
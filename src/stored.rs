@@ -0,0 +1,129 @@
+use super::{decode, encode, Config, DecodeError, EncodeError};
+
+// Method tag preceding the data
+const METHOD_STORED: u8 = 0;
+const METHOD_COMPRESSED: u8 = 1;
+
+/// Outcome of `compress_if_smaller()`. Both hold the data to keep,
+/// starting with the method tag that `decompress_if_smaller()` reads
+#[derive(Debug, PartialEq, Eq)]
+pub enum CompressResult<'a> {
+    /// The input did not compress, and is stored as is after the tag
+    Stored(&'a [u8]),
+    /// The compressed input after the tag
+    Compressed(&'a [u8]),
+}
+
+impl<'a> CompressResult<'a> {
+    /// Returns the data to keep, including the method tag
+    pub fn data(&self) -> &'a [u8] {
+        match self {
+            CompressResult::Stored(data) | CompressResult::Compressed(data) => data,
+        }
+    }
+}
+
+/// Compression call that keeps the input uncompressed when compressing
+/// would not make it smaller, like for already compressed data, so that
+/// the result is never more than 1 byte longer than the input. A method
+/// tag byte precedes the data, so `output` needs room for the input and
+/// the tag to be sure to succeed. Decompress with `decompress_if_smaller()`
+pub fn compress_if_smaller<'a>(
+    input: &[u8],
+    output: &'a mut [u8],
+    cfg: &Config,
+) -> Result<CompressResult<'a>, EncodeError> {
+    let (tag, rest) = output.split_first_mut().ok_or(EncodeError::OutputFull)?;
+    // Only compressed data shorter than the input is of use
+    let limit = rest.len().min(input.len().saturating_sub(1));
+    match encode(input, &mut rest[..limit], cfg).map(|data| data.len()) {
+        Ok(len) if len < input.len() => {
+            *tag = METHOD_COMPRESSED;
+            Ok(CompressResult::Compressed(&output[..1 + len]))
+        }
+        Err(err) if err != EncodeError::OutputFull => Err(err),
+        _ if rest.len() >= input.len() => {
+            rest[..input.len()].copy_from_slice(input);
+            *tag = METHOD_STORED;
+            Ok(CompressResult::Stored(&output[..1 + input.len()]))
+        }
+        _ => Err(EncodeError::OutputFull),
+    }
+}
+
+/// Decompression call for data produced by `compress_if_smaller()`,
+/// returning the original data. A missing or unknown method tag results in
+/// `DecodeError::BadHeader`
+pub fn decompress_if_smaller<'a>(
+    input: &[u8],
+    output: &'a mut [u8],
+    cfg: &Config,
+) -> Result<&'a [u8], DecodeError> {
+    match input.split_first() {
+        Some((&METHOD_STORED, data)) => {
+            let dst = output
+                .get_mut(..data.len())
+                .ok_or(DecodeError::OutputFull {
+                    written: 0,
+                    input_bit: 8,
+                })?;
+            dst.copy_from_slice(data);
+            Ok(dst)
+        }
+        Some((&METHOD_COMPRESSED, data)) => {
+            decode(data, output, cfg).map_err(|err| err.offset_input(1))
+        }
+        _ => Err(DecodeError::BadHeader),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{compress_if_smaller, decompress_if_smaller, CompressResult};
+    use crate::{Config, DecodeError, EncodeError};
+
+    #[test]
+    fn round_trip() {
+        let cfg = Config::default();
+        let mut dst1 = [0; 40];
+        let mut dst2 = [0; 40];
+        let src = b"abcabcabcabcabcabcabcabc";
+        let res = compress_if_smaller(src, &mut dst1, &cfg).unwrap();
+        assert!(matches!(res, CompressResult::Compressed(_)));
+        assert!(res.data().len() < src.len());
+        assert_eq!(
+            decompress_if_smaller(res.data(), &mut dst2, &cfg).unwrap(),
+            src
+        );
+        // 9 bits per literal never pay off
+        for src in [&b"abcdefgh"[..], b""] {
+            let res = compress_if_smaller(src, &mut dst1, &cfg).unwrap();
+            assert_eq!(res.data()[0], 0);
+            assert!(matches!(res, CompressResult::Stored(data) if &data[1..] == src));
+            assert_eq!(
+                decompress_if_smaller(res.data(), &mut dst2, &cfg).unwrap(),
+                src
+            );
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let cfg = Config::default();
+        let mut dst = [0; 8];
+        let res = compress_if_smaller(b"abcdefgh", &mut dst, &cfg);
+        assert_eq!(res, Err(EncodeError::OutputFull));
+        let res = compress_if_smaller(b"", &mut [], &cfg);
+        assert_eq!(res, Err(EncodeError::OutputFull));
+        for src in [&[][..], &[2, 0]] {
+            let res = decompress_if_smaller(src, &mut dst, &cfg);
+            assert_eq!(res, Err(DecodeError::BadHeader));
+        }
+        let res = decompress_if_smaller(&[0; 10], &mut dst, &cfg);
+        let full = DecodeError::OutputFull {
+            written: 0,
+            input_bit: 8,
+        };
+        assert_eq!(res, Err(full));
+    }
+}
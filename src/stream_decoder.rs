@@ -0,0 +1,617 @@
+#[cfg(feature = "trace")]
+use super::decoder::DecodeEvent;
+use super::decoder::{trace, DecodeError, DecodeOptions, HSDstate, MismatchCheck};
+use super::window::Window;
+use super::Config;
+
+/// Reason for `StreamDecoder::poll_status()` to return, with the number of
+/// bytes written
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PollResult {
+    /// All sunk data was decoded, except for an incomplete symbol
+    NeedsInput(usize),
+    /// The output is full, and more data may be decoded without sinking any
+    OutputFull(usize),
+}
+
+impl PollResult {
+    /// Returns the number of bytes written
+    pub fn written(&self) -> usize {
+        match self {
+            PollResult::NeedsInput(written) | PollResult::OutputFull(written) => *written,
+        }
+    }
+}
+
+/// Incremental decoder that accepts compressed data in arbitrary chunks
+/// and produces decompressed data into caller supplied buffers.
+///
+/// Back-references are resolved against a ring buffer holding the last
+/// `1 << window_sz2` decoded bytes, so the complete output never has to
+/// be resident in memory. The ring buffer storage is provided by the caller,
+/// which keeps the decoder usable without an allocator.
+pub struct StreamDecoder<B> {
+    cfg: Config,
+    opts: DecodeOptions,
+    window: Window<B>,
+    state: HSDstate,
+    consumed: usize,     // Total number of bytes sunk
+    symbol_start: usize, // Input bit position of the current symbol
+    mismatch: MismatchCheck,
+    output_index: usize, // Backref distance
+    output_count: usize, // Remaining backref bytes
+    bit_buf: u32,
+    num_bits: u8,
+}
+
+impl<B: AsMut<[u8]>> StreamDecoder<B> {
+    /// Creates a new streaming decoder using `window` as ring buffer storage.
+    /// The ring buffer is cleared before use
+    ///
+    /// # Panics
+    ///
+    /// Panics if `window` is shorter than `1 << window_sz2` bytes
+    pub fn new(window: B, cfg: &Config) -> Self {
+        StreamDecoder::with_options(window, cfg, &Default::default())
+    }
+
+    /// Creates a new streaming decoder like `new()`, using the given decoder options
+    ///
+    /// # Panics
+    ///
+    /// Panics if `window` is shorter than `1 << window_sz2` bytes
+    pub fn with_options(window: B, cfg: &Config, opts: &DecodeOptions) -> Self {
+        StreamDecoder {
+            cfg: *cfg,
+            opts: *opts,
+            window: Window::new(window, cfg),
+            state: HSDstate::HSDSTagBit,
+            consumed: 0,
+            symbol_start: 0,
+            mismatch: Default::default(),
+            output_index: 0,
+            output_count: 0,
+            bit_buf: 0,
+            num_bits: 0,
+        }
+    }
+
+    /// Feeds compressed data to the decoder, returning the number of bytes
+    /// that were accepted. Only a few bytes are buffered internally,
+    /// so `poll()` must be called to make room for more input
+    pub fn sink(&mut self, input: &[u8]) -> usize {
+        let mut consumed = 0;
+        for byte in input {
+            if self.num_bits > 24 {
+                break;
+            }
+            self.bit_buf = (self.bit_buf << 8) | *byte as u32;
+            self.num_bits += 8;
+            consumed += 1;
+        }
+        self.consumed += consumed;
+        consumed
+    }
+
+    /// Decodes as much of the sunk data as possible into `output`,
+    /// returning the number of bytes written. If fewer than `output.len()`
+    /// bytes were written, the decoder needs more input to make progress
+    pub fn poll(&mut self, output: &mut [u8]) -> Result<usize, DecodeError> {
+        self.poll_status(output).map(|res| res.written())
+    }
+
+    /// Decodes like `poll()`, also telling why decoding stopped, which
+    /// `poll()` leaves open when the output was filled exactly. After
+    /// `PollResult::OutputFull` the caller drains the output and polls
+    /// again, possibly into the same buffer, as the window is retained,
+    /// while `PollResult::NeedsInput` asks for more sunk data first
+    pub fn poll_status(&mut self, output: &mut [u8]) -> Result<PollResult, DecodeError> {
+        let mut written = 0;
+        loop {
+            let next = match self.state {
+                HSDstate::HSDSTagBit => self.st_tag_bit(),
+                HSDstate::HSDSYieldLiteral => self.st_yield_literal(output, &mut written),
+                HSDstate::HSDSBackrefIndexMsb => self.st_backref_index_msb(),
+                HSDstate::HSDSBackrefIndexLsb => self.st_backref_index_lsb(),
+                HSDstate::HSDSBackrefCountMsb => self.st_backref_count_msb(),
+                HSDstate::HSDSBackrefCountLsb => self.st_backref_count_lsb(),
+                HSDstate::HSDSYieldBackref => self.st_yield_backref(output, &mut written),
+                HSDstate::HSDSNeedMoreData
+                | HSDstate::OutputFull
+                | HSDstate::IllegalBackref
+                | HSDstate::ConfigMismatch => {
+                    unreachable!()
+                }
+            };
+            trace!(
+                self.opts,
+                DecodeEvent::State {
+                    name: next.name(),
+                    bit_index: self.consumed * 8 - self.num_bits as usize,
+                }
+            );
+            match next {
+                HSDstate::IllegalBackref => {
+                    return Err(DecodeError::IllegalBackref {
+                        input_bit: self.symbol_start,
+                        output_pos: self.window.pushed(),
+                    })
+                }
+                HSDstate::ConfigMismatch => return Err(DecodeError::LikelyConfigMismatch),
+                // Suspend, and resume in the current state on the next call
+                HSDstate::HSDSNeedMoreData => return Ok(PollResult::NeedsInput(written)),
+                HSDstate::OutputFull => return Ok(PollResult::OutputFull(written)),
+                state => self.state = state,
+            }
+        }
+    }
+
+    /// Prepares the decoder for decoding a new, independent frame, reusing
+    /// the window buffer. History is discarded, and the window is cleared
+    /// as by `new()`, so the frame is decoded just like on its own. Any
+    /// sunk data not yet decoded is discarded as well, including the
+    /// padding at the end of the previous frame
+    pub fn reset(&mut self) {
+        self.window.clear();
+        self.state = HSDstate::HSDSTagBit;
+        self.consumed = 0;
+        self.symbol_start = 0;
+        self.mismatch = Default::default();
+        self.output_index = 0;
+        self.output_count = 0;
+        self.bit_buf = 0;
+        self.num_bits = 0;
+    }
+
+    /// Returns the total number of compressed bytes accepted by `sink()`
+    /// since creation or the last `reset()`
+    pub fn input_consumed(&self) -> usize {
+        self.consumed
+    }
+
+    /// Returns the total number of decompressed bytes produced by `poll()`
+    /// since creation or the last `reset()`
+    pub fn output_produced(&self) -> usize {
+        self.window.pushed()
+    }
+
+    /// Releases the ring buffer storage
+    pub fn into_inner(self) -> B {
+        self.window.into_inner()
+    }
+
+    fn get_bits(&mut self, count: u8) -> Option<u16> {
+        if self.num_bits < count {
+            return None;
+        }
+        self.num_bits -= count;
+        let bits = (self.bit_buf >> self.num_bits) & ((1 << count) - 1);
+        Some(bits as u16)
+    }
+
+    fn push_byte(&mut self, byte: u8, output: &mut [u8], written: &mut usize) {
+        self.window.push(byte);
+        output[*written] = byte;
+        *written += 1;
+    }
+
+    fn st_tag_bit(&mut self) -> HSDstate {
+        self.symbol_start = self.consumed * 8 - self.num_bits as usize;
+        match self.get_bits(1) {
+            Some(0) => {
+                self.output_index = 0;
+                if self.cfg.window_sz2 > 8 {
+                    HSDstate::HSDSBackrefIndexMsb
+                } else {
+                    HSDstate::HSDSBackrefIndexLsb
+                }
+            }
+            Some(_) => HSDstate::HSDSYieldLiteral,
+            None => HSDstate::HSDSNeedMoreData,
+        }
+    }
+
+    fn st_yield_literal(&mut self, output: &mut [u8], written: &mut usize) -> HSDstate {
+        if *written >= output.len() {
+            return HSDstate::OutputFull;
+        }
+        match self.get_bits(8) {
+            Some(byte) => {
+                trace!(
+                    self.opts,
+                    DecodeEvent::Literal {
+                        pos: self.window.pushed(),
+                        byte: byte as u8,
+                    }
+                );
+                self.push_byte(byte as u8, output, written);
+                HSDstate::HSDSTagBit
+            }
+            None => HSDstate::HSDSNeedMoreData,
+        }
+    }
+
+    fn st_backref_index_msb(&mut self) -> HSDstate {
+        let bit_ct = self.cfg.window_sz2 - 8;
+        match self.get_bits(bit_ct) {
+            Some(idx) => {
+                self.output_index = (idx as usize) << 8;
+                HSDstate::HSDSBackrefIndexLsb
+            }
+            None => HSDstate::HSDSNeedMoreData,
+        }
+    }
+
+    fn st_backref_index_lsb(&mut self) -> HSDstate {
+        let bit_ct = self.cfg.window_sz2.min(8);
+        match self.get_bits(bit_ct) {
+            Some(idx) => {
+                self.output_index = (self.output_index | idx as usize) + 1;
+                self.output_count = 0;
+                if self.cfg.lookahead_sz2 > 8 {
+                    HSDstate::HSDSBackrefCountMsb
+                } else {
+                    HSDstate::HSDSBackrefCountLsb
+                }
+            }
+            None => HSDstate::HSDSNeedMoreData,
+        }
+    }
+
+    fn st_backref_count_msb(&mut self) -> HSDstate {
+        let bit_ct = self.cfg.lookahead_sz2 - 8;
+        match self.get_bits(bit_ct) {
+            Some(cnt) => {
+                self.output_count = (cnt as usize) << 8;
+                HSDstate::HSDSBackrefCountLsb
+            }
+            None => HSDstate::HSDSNeedMoreData,
+        }
+    }
+
+    fn st_backref_count_lsb(&mut self) -> HSDstate {
+        let bit_ct = self.cfg.lookahead_sz2.min(8);
+        match self.get_bits(bit_ct) {
+            Some(cnt) => {
+                self.output_count = (self.output_count | cnt as usize) + 1;
+                trace!(
+                    self.opts,
+                    DecodeEvent::Backref {
+                        pos: self.window.pushed(),
+                        distance: self.output_index,
+                        count: self.output_count,
+                    }
+                );
+                let pos = self.window.pushed();
+                let before_start = self.output_index > pos;
+                if self.opts.mismatch_check && self.mismatch.backref(pos, before_start) {
+                    return HSDstate::ConfigMismatch;
+                }
+                HSDstate::HSDSYieldBackref
+            }
+            None => HSDstate::HSDSNeedMoreData,
+        }
+    }
+
+    fn st_yield_backref(&mut self, output: &mut [u8], written: &mut usize) -> HSDstate {
+        if self.output_index > self.window.pushed() && !self.opts.zero_window {
+            return HSDstate::IllegalBackref;
+        }
+        while self.output_count > 0 {
+            if *written >= output.len() {
+                return HSDstate::OutputFull;
+            }
+            // The ring buffer starts out zeroed, which matches the empty
+            // window of the C implementation
+            let byte = self.window.get_back(self.output_index);
+            self.push_byte(byte, output, written);
+            self.output_count -= 1;
+        }
+        HSDstate::HSDSTagBit
+    }
+}
+
+/// Decodes `input` lazily, yielding one decompressed byte at a time.
+/// Back-references are resolved against `window`, which must be at least
+/// `1 << window_sz2` bytes, so no output buffer for the complete data is needed
+///
+/// # Panics
+///
+/// Panics if `window` is shorter than `1 << window_sz2` bytes
+pub fn decode_iter<'a>(input: &'a [u8], window: &'a mut [u8], cfg: &Config) -> DecodeIter<'a> {
+    DecodeIter {
+        decoder: StreamDecoder::new(window, cfg),
+        input,
+        failed: false,
+    }
+}
+
+/// Iterator over decompressed bytes, created by `decode_iter()`
+pub struct DecodeIter<'a> {
+    decoder: StreamDecoder<&'a mut [u8]>,
+    input: &'a [u8],
+    failed: bool,
+}
+
+impl Iterator for DecodeIter<'_> {
+    type Item = Result<u8, DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let mut byte = [0];
+        loop {
+            match self.decoder.poll(&mut byte) {
+                Ok(0) => {
+                    if self.input.is_empty() {
+                        return None;
+                    }
+                    let n = self.decoder.sink(self.input);
+                    self.input = &self.input[n..];
+                }
+                Ok(_) => return Some(Ok(byte[0])),
+                Err(e) => {
+                    self.failed = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode_iter, PollResult, StreamDecoder};
+    use crate::{decode, encode, encode_with_stats, Config};
+
+    fn stream_decode<'a>(src: &[u8], dst: &'a mut [u8], chunk: usize, cfg: &Config) -> &'a [u8] {
+        let mut window = [0; 1 << 11];
+        let mut dec = StreamDecoder::new(&mut window[..], cfg);
+        let mut total = 0;
+        for part in src.chunks(chunk) {
+            let mut consumed = 0;
+            while consumed < part.len() {
+                consumed += dec.sink(&part[consumed..]);
+                loop {
+                    let end = dst.len().min(total + chunk);
+                    let n = dec.poll(&mut dst[total..end]).unwrap();
+                    total += n;
+                    if n == 0 {
+                        break;
+                    }
+                }
+            }
+        }
+        assert_eq!(dec.input_consumed(), src.len());
+        assert_eq!(dec.output_produced(), total);
+        &dst[..total]
+    }
+
+    #[test]
+    fn clib_compatibility() {
+        let src = hex_literal::hex!("90D4B2B549A408057C003E0100C9811B7CA05F1817C002DA5F04025F0005");
+        let cfg = Config::new(11, 4).unwrap();
+        let mut dst1 = [0; 100];
+        let mut dst2 = [0; 100];
+        let expected = decode(&src, &mut dst1, &cfg).unwrap();
+        for chunk in [1, 2, 3, 7, 100] {
+            assert_eq!(stream_decode(&src, &mut dst2, chunk, &cfg), expected);
+        }
+    }
+
+    #[test]
+    fn iterator() {
+        let src = hex_literal::hex!("90D4B2B549A408057C003E0100C9811B7CA05F1817C002DA5F04025F0005");
+        let cfg = Config::new(11, 4).unwrap();
+        let mut dst = [0; 100];
+        let expected = decode(&src, &mut dst, &cfg).unwrap();
+        let mut window = [0; 1 << 11];
+        let mut iter = decode_iter(&src, &mut window, &cfg);
+        for byte in expected {
+            assert_eq!(iter.next().unwrap().unwrap(), *byte);
+        }
+        assert!(iter.next().is_none());
+
+        let mut window = [0; 1 << 11];
+        let head = decode_iter(&src, &mut window, &cfg).take(5);
+        assert!(head.map(|b| b.unwrap()).eq(expected[..5].iter().copied()));
+    }
+
+    #[test]
+    fn backref_before_start() {
+        use crate::{DecodeError, DecodeOptions};
+        // Backref of distance 1 as the very first symbol
+        let src = [0x00, 0x00];
+        let cfg = Config::new(11, 4).unwrap();
+        let mut window = [0; 1 << 11];
+        let mut dst = [0xff; 4];
+        let mut dec = StreamDecoder::new(&mut window[..], &cfg);
+        assert_eq!(dec.sink(&src), 2);
+        assert_eq!(dec.poll(&mut dst).unwrap(), 1);
+        assert_eq!(dst[0], 0);
+
+        let opts = DecodeOptions::new().with_zero_window(false);
+        let mut dec = StreamDecoder::with_options(&mut window[..], &cfg, &opts);
+        assert_eq!(dec.sink(&src), 2);
+        let illegal = DecodeError::IllegalBackref {
+            input_bit: 0,
+            output_pos: 0,
+        };
+        assert_eq!(dec.poll(&mut dst), Err(illegal));
+    }
+
+    #[test]
+    fn window_wraparound() {
+        let mut src = [0; 600];
+        for (i, b) in src.iter_mut().enumerate() {
+            *b = (i % 7) as u8 + (i / 100) as u8;
+        }
+        let cfg = Config::new(8, 4).unwrap();
+        let mut compressed = [0; 800];
+        let mut dst = [0; 600];
+        let compressed = encode(&src, &mut compressed, &cfg).unwrap();
+        for chunk in [1, 5, 64] {
+            assert_eq!(stream_decode(compressed, &mut dst, chunk, &cfg), &src[..]);
+        }
+    }
+
+    #[test]
+    fn reset() {
+        let cfg = Config::new(8, 4).unwrap();
+        let mut window = [0; 256];
+        let mut dec = StreamDecoder::new(&mut window[..], &cfg);
+        let mut compressed = [0; 64];
+        let mut dst = [0; 64];
+        // An unfinished backref is left in the first frame
+        let first = encode(b"abcabcabcabcabc", &mut compressed, &cfg).unwrap();
+        let len = first.len();
+        dec.sink(&first[..len - 1]);
+        while dec.poll(&mut dst).unwrap() > 0 {}
+        for frame in [&b"xyzxyzxyzxyz"[..], b"\0\0\0\0\0\0\0\0"] {
+            dec.reset();
+            let compressed = encode(frame, &mut compressed, &cfg).unwrap();
+            let mut total = 0;
+            for byte in compressed {
+                assert_eq!(dec.sink(&[*byte]), 1);
+                total += dec.poll(&mut dst[total..]).unwrap();
+            }
+            assert_eq!(&dst[..total], frame);
+            assert_eq!(dec.input_consumed(), compressed.len());
+            assert_eq!(dec.output_produced(), frame.len());
+        }
+    }
+
+    #[test]
+    fn max_window_distance() {
+        // The final bytes refer 65536 bytes back, the whole 16 bit window
+        let mut src = [0; (1 << 16) + 4];
+        src[..4].copy_from_slice(&[1, 2, 3, 4]);
+        src[1 << 16..].copy_from_slice(&[1, 2, 3, 4]);
+        let cfg = Config::new(16, 4).unwrap();
+        let mut compressed = [0; 16384];
+        let (compressed, stats) = encode_with_stats(&src, &mut compressed, &cfg).unwrap();
+        // Only the leading bytes up to the first 0 are literals
+        assert_eq!(stats.literals, 5);
+        let mut dec = StreamDecoder::new([0; 1 << 16], &cfg);
+        let mut out = [0; 256];
+        let mut total = 0;
+        let mut consumed = 0;
+        loop {
+            consumed += dec.sink(&compressed[consumed..]);
+            let n = dec.poll(&mut out).unwrap();
+            if n == 0 && consumed == compressed.len() {
+                break;
+            }
+            assert_eq!(out[..n], src[total..total + n]);
+            total += n;
+        }
+        assert_eq!(total, src.len());
+    }
+
+    #[test]
+    fn bounded_memory() {
+        // 64 kB of output passes through a 256 byte window and a 16 byte buffer
+        let src: [u8; 65536] = core::array::from_fn(|i| (i * i % 251 % 13) as u8);
+        let cfg = Config::new(8, 4).unwrap();
+        let mut compressed = [0; 65536];
+        let compressed = encode(&src, &mut compressed, &cfg).unwrap();
+        let mut dec = StreamDecoder::new([0; 256], &cfg);
+        let mut out = [0; 16];
+        let mut total = 0;
+        let mut consumed = 0;
+        loop {
+            consumed += dec.sink(&compressed[consumed..]);
+            let n = dec.poll(&mut out).unwrap();
+            if n == 0 && consumed == compressed.len() {
+                break;
+            }
+            assert_eq!(out[..n], src[total..total + n]);
+            total += n;
+        }
+        assert_eq!(total, src.len());
+    }
+
+    #[test]
+    fn poll_status() {
+        let src: [u8; 4000] = core::array::from_fn(|i| (i * i % 251 % 13) as u8);
+        let cfg = Config::new(8, 4).unwrap();
+        let mut compressed = [0; 4000];
+        let compressed = encode(&src, &mut compressed, &cfg).unwrap();
+        let mut dec = StreamDecoder::new([0; 256], &cfg);
+        let mut out = [0; 5];
+        let (mut total, mut consumed, mut suspended) = (0, 0, 0);
+        loop {
+            // Sink only when asked, draining the same buffer in between
+            match dec.poll_status(&mut out).unwrap() {
+                PollResult::OutputFull(n) => {
+                    assert_eq!(n, out.len());
+                    assert_eq!(out, src[total..total + n]);
+                    total += n;
+                    suspended += 1;
+                }
+                PollResult::NeedsInput(n) => {
+                    assert_eq!(out[..n], src[total..total + n]);
+                    total += n;
+                    if consumed == compressed.len() {
+                        break;
+                    }
+                    consumed += dec.sink(&compressed[consumed..]);
+                }
+            }
+        }
+        assert_eq!(total, src.len());
+        assert!(suspended >= src.len() / out.len() / 2);
+        assert_eq!(dec.poll_status(&mut out), Ok(PollResult::NeedsInput(0)));
+    }
+
+    #[test]
+    fn no_stale_fields() {
+        // Backrefs with all field bits set alternate with ones with few set,
+        // separated by literals, with split and unsplit index and count
+        for (window, lookahead) in [(8, 4), (8, 7), (11, 4), (12, 10)] {
+            let cfg = Config::new(window, lookahead).unwrap();
+            let (wsize, maxlen) = (1usize << window, 1usize << lookahead);
+            let mut stream = [0u8; 8000];
+            let mut bits = 0;
+            let mut put = |val: usize, count: u8| {
+                for i in (0..count).rev() {
+                    stream[bits / 8] |= (((val >> i) & 1) as u8) << (7 - bits % 8);
+                    bits += 1;
+                }
+            };
+            let mut expected = [0u8; 20000];
+            let mut len = 0;
+            for i in 0..wsize {
+                put(0x100 | (i * 7) & 0xff, 9);
+                expected[len] = (i * 7) as u8;
+                len += 1;
+            }
+            for k in 0..20 {
+                let (dist, count) = if k % 2 == 0 { (wsize, maxlen) } else { (k, 1) };
+                put(0, 1);
+                put(dist - 1, window);
+                put(count - 1, lookahead);
+                for _ in 0..count {
+                    expected[len] = expected[len - dist];
+                    len += 1;
+                }
+                put(0x100 | k, 9);
+                expected[len] = k as u8;
+                len += 1;
+            }
+            let src = &stream[..bits.div_ceil(8)];
+            let mut dst = [0; 20000];
+            assert_eq!(decode(src, &mut dst, &cfg).unwrap(), &expected[..len]);
+            // The streaming decoder suspends within the fields a byte at a time
+            let mut window = [0; 1 << 12];
+            let mut dec = StreamDecoder::new(&mut window[..], &cfg);
+            let mut total = 0;
+            for byte in src {
+                assert_eq!(dec.sink(&[*byte]), 1);
+                total += dec.poll(&mut dst[total..]).unwrap();
+            }
+            assert_eq!(&dst[..total], &expected[..len]);
+        }
+    }
+}
@@ -0,0 +1,595 @@
+use super::encoder::{find_backref, padding, EncodeError, EncodeOptions};
+use super::Config;
+
+/// Incremental encoder that accepts uncompressed data in arbitrary chunks
+/// and produces compressed data into caller supplied buffers.
+///
+/// Input is collected in a buffer provided by the caller, holding the
+/// search window followed by not yet encoded data. The buffer must be at
+/// least `(1 << window_sz2) + (1 << lookahead_sz2)` bytes, and making it
+/// larger reduces how often the window has to be moved to the front.
+/// Lazy matching needs one more byte of lookahead, and thereby buffer.
+/// The produced stream is identical to what `encode()` produces for the
+/// concatenation of all sunk data.
+pub struct StreamEncoder<B> {
+    cfg: Config,
+    opts: EncodeOptions,
+    buffer: B,
+    head: usize,   // Next position in buffer to encode
+    filled: usize, // Number of valid bytes in buffer
+    finishing: bool,
+    flushing: bool,  // Encode all sunk data, rather than holding back a lookahead
+    consumed: usize, // Total number of bytes sunk
+    produced: usize, // Total number of bytes polled
+    bit_buf: u64,
+    num_bits: u8,
+    failed: bool, // Set once a field did not fit its width
+}
+
+impl<B: AsMut<[u8]>> StreamEncoder<B> {
+    /// Creates a new streaming encoder using `buffer` as window storage
+    ///
+    /// # Panics
+    ///
+    /// Panics if `buffer` is shorter than `(1 << window_sz2) + (1 << lookahead_sz2)` bytes
+    pub fn new(buffer: B, cfg: &Config) -> Self {
+        StreamEncoder::with_options(buffer, cfg, &Default::default())
+    }
+
+    /// Creates a new streaming encoder like `new()`, using the given encoder options
+    ///
+    /// # Panics
+    ///
+    /// Panics if `buffer` is shorter than `(1 << window_sz2) + (1 << lookahead_sz2)` bytes,
+    /// plus one byte if lazy matching is enabled
+    pub fn with_options(mut buffer: B, cfg: &Config, opts: &EncodeOptions) -> Self {
+        let size =
+            (1 << cfg.window_sz2 as usize) + (1 << cfg.lookahead_sz2 as usize) + opts.lazy as usize;
+        assert!(buffer.as_mut().len() >= size, "Window buffer is too small");
+        StreamEncoder {
+            cfg: *cfg,
+            opts: *opts,
+            buffer,
+            head: 0,
+            filled: 0,
+            finishing: false,
+            flushing: false,
+            consumed: 0,
+            produced: 0,
+            bit_buf: 0,
+            num_bits: 0,
+            failed: false,
+        }
+    }
+
+    /// Feeds data to the encoder, returning the number of bytes that were
+    /// accepted. When the internal buffer is full, `poll()` must be called
+    /// to make room for more input. No input is accepted after `finish()`,
+    /// or once encoding has failed, see `error()`
+    pub fn sink(&mut self, input: &[u8]) -> usize {
+        if self.finishing || self.failed {
+            return 0;
+        }
+        let buf = self.buffer.as_mut();
+        if self.filled == buf.len() {
+            // Move the window to the front, keeping the history needed for backrefs
+            let drop = self.head.saturating_sub(1 << self.cfg.window_sz2 as usize);
+            buf.copy_within(drop..self.filled, 0);
+            self.head -= drop;
+            self.filled -= drop;
+        }
+        let n = input.len().min(buf.len() - self.filled);
+        buf[self.filled..self.filled + n].copy_from_slice(&input[..n]);
+        self.filled += n;
+        self.consumed += n;
+        n
+    }
+
+    /// Encodes as much of the sunk data as possible into `output`,
+    /// returning the number of bytes written. Data near the end of the
+    /// buffered input is held back until more input arrives or `finish()`
+    /// has been called, so that matches may span `sink()` calls. Nothing
+    /// more is written once encoding has failed, see `error()`
+    pub fn poll(&mut self, output: &mut [u8]) -> usize {
+        self.run(output, usize::MAX)
+    }
+
+    /// Encodes like `poll()`, but with a bounded amount of work, so that
+    /// a caller can yield or pet a watchdog between steps. A step encodes
+    /// at most `max_symbols` symbols, or one more if that is needed to
+    /// complete a byte of output, so each step that does any work writes
+    /// at least one byte. Encoding a symbol takes one match search, which
+    /// compares against at most `1 << window_sz2` earlier positions, or
+    /// `EncodeOptions::with_max_chain_len()` of them, each comparison of
+    /// at most `1 << lookahead_sz2` bytes. Returns the number of bytes
+    /// written, which is 0 when no work is left until more input is sunk,
+    /// `finish()` is called or `output` has room
+    pub fn step(&mut self, output: &mut [u8], max_symbols: usize) -> usize {
+        self.run(output, max_symbols)
+    }
+
+    fn run(&mut self, output: &mut [u8], max_symbols: usize) -> usize {
+        let mut written = 0;
+        let mut symbols = 0;
+        loop {
+            if self.failed {
+                return written;
+            }
+            while self.num_bits >= 8 {
+                if written >= output.len() {
+                    return written;
+                }
+                output[written] = (self.bit_buf >> (self.num_bits - 8)) as u8;
+                written += 1;
+                self.produced += 1;
+                self.num_bits -= 8;
+            }
+            // Once the budget is spent, one more symbol may complete a byte
+            if symbols >= max_symbols && (written > 0 || symbols > max_symbols) {
+                return written;
+            }
+            let avail = self.filled - self.head;
+            // Lazy matching also searches at the following byte
+            let lookahead = (1 << self.cfg.lookahead_sz2 as usize) + self.opts.lazy as usize;
+            if avail == 0 {
+                self.flushing = false;
+            }
+            if avail == 0 || (!self.finishing && !self.flushing && avail < lookahead) {
+                if self.finishing && self.num_bits > 0 {
+                    // Pad the final byte
+                    let pad_bits = 8 - self.num_bits;
+                    self.emit_bits(padding(&self.cfg, pad_bits), pad_bits);
+                    continue;
+                }
+                return written;
+            }
+            let buf = &self.buffer.as_mut()[..self.filled];
+            match find_backref(buf, &[], None, self.head, &self.cfg, &self.opts) {
+                Some((rel, len)) => {
+                    self.emit_bits(0, 1);
+                    self.emit_bits((rel - 1) as u16, self.cfg.window_sz2);
+                    self.emit_bits((len - 1) as u16, self.cfg.lookahead_sz2);
+                    self.head += len as usize;
+                }
+                None => {
+                    let code = buf[self.head] as u16 | 0x0100;
+                    self.emit_bits(code, 9);
+                    self.head += 1;
+                }
+            }
+            symbols += 1;
+        }
+    }
+
+    /// Makes the following `poll()` calls encode all data sunk so far,
+    /// rather than holding back the end of it for matches that may continue
+    /// in the next `sink()`, so that a latency sensitive consumer gets it
+    /// sooner. The stream stays continuous, and decodes as one with the data
+    /// sunk later, at the cost of matches not spanning the flush. All bytes
+    /// of the flushed data become available except for up to 7 bits of its
+    /// last symbol, as the stream can only be padded at its end: padding
+    /// bits midway would be decoded as symbols. Flushing including those
+    /// bits takes `finish()`, and `reset()` to start a new stream
+    pub fn partial_flush(&mut self) {
+        self.flushing = true;
+    }
+
+    /// Notifies the encoder that all input has been sunk. Returns true once
+    /// all compressed data including the final padded byte has been polled,
+    /// or once encoding has failed, which `error()` tells apart
+    pub fn finish(&mut self) -> bool {
+        self.finishing = true;
+        self.failed || (self.head == self.filled && self.num_bits == 0)
+    }
+
+    /// Returns `EncodeError::Internal` once the encoder produced a field
+    /// that does not fit its width, after which it stays failed until
+    /// `reset()`. This points to a bug in the encoder, caught by an
+    /// assertion in debug builds
+    pub fn error(&self) -> Option<EncodeError> {
+        self.failed.then_some(EncodeError::Internal)
+    }
+
+    /// Prepares the encoder for compressing a new, independent frame,
+    /// reusing the window buffer. History is discarded, so each frame can
+    /// be decoded on its own. Any data not yet polled is discarded as well
+    pub fn reset(&mut self) {
+        self.head = 0;
+        self.filled = 0;
+        self.finishing = false;
+        self.flushing = false;
+        self.consumed = 0;
+        self.produced = 0;
+        self.bit_buf = 0;
+        self.num_bits = 0;
+        self.failed = false;
+    }
+
+    /// Returns the total number of uncompressed bytes accepted by `sink()`
+    /// since creation or the last `reset()`
+    pub fn input_consumed(&self) -> usize {
+        self.consumed
+    }
+
+    /// Returns the total number of compressed bytes produced by `poll()`
+    /// since creation or the last `reset()`
+    pub fn output_produced(&self) -> usize {
+        self.produced
+    }
+
+    /// Releases the window buffer storage
+    pub fn into_inner(self) -> B {
+        self.buffer
+    }
+
+    fn emit_bits(&mut self, val: u16, bit_cnt: u8) {
+        // An out of range value would corrupt the neighbouring fields
+        debug_assert!((val as u32) < (1 << bit_cnt as u32));
+        if val as u32 >= 1 << bit_cnt as u32 {
+            self.failed = true;
+            return;
+        }
+        self.bit_buf = (self.bit_buf << bit_cnt) | val as u64;
+        self.num_bits += bit_cnt;
+    }
+}
+
+/// Encoder for producers handing out one byte at a time, like a sensor
+/// sampling loop, compressing into a fixed output buffer. This wraps a
+/// `StreamEncoder`, whose buffer is passed to `new()`, and writes the
+/// compressed data to the output as the buffer fills up. It also
+/// implements `Extend<u8>`, which stops at the first byte that does not
+/// fit, reported by `finish()`
+pub struct PushEncoder<'o, B> {
+    encoder: StreamEncoder<B>,
+    output: &'o mut [u8],
+    written: usize,
+    full: bool,
+}
+
+impl<'o, B: AsMut<[u8]>> PushEncoder<'o, B> {
+    /// Creates an encoder compressing into `output`, using `buffer` as
+    /// window storage like `StreamEncoder::new()`
+    ///
+    /// # Panics
+    ///
+    /// Panics if `buffer` is shorter than `(1 << window_sz2) + (1 << lookahead_sz2)` bytes
+    pub fn new(buffer: B, output: &'o mut [u8], cfg: &Config) -> Self {
+        PushEncoder::with_options(buffer, output, cfg, &Default::default())
+    }
+
+    /// Creates an encoder like `new()`, using the given encoder options
+    ///
+    /// # Panics
+    ///
+    /// Panics if `buffer` is shorter than `(1 << window_sz2) + (1 << lookahead_sz2)` bytes,
+    /// plus one byte if lazy matching is enabled
+    pub fn with_options(
+        buffer: B,
+        output: &'o mut [u8],
+        cfg: &Config,
+        opts: &EncodeOptions,
+    ) -> Self {
+        PushEncoder {
+            encoder: StreamEncoder::with_options(buffer, cfg, opts),
+            output,
+            written: 0,
+            full: false,
+        }
+    }
+
+    /// Adds a byte to the data being compressed. Returns
+    /// `EncodeError::OutputFull` if the output has no room for the data
+    /// compressed so far, after which no more bytes are accepted, or the
+    /// error of the `StreamEncoder` if encoding failed
+    pub fn push(&mut self, byte: u8) -> Result<(), EncodeError> {
+        if let Some(err) = self.encoder.error() {
+            return Err(err);
+        }
+        if self.full {
+            return Err(EncodeError::OutputFull);
+        }
+        while self.encoder.sink(&[byte]) == 0 {
+            // The buffer is full, so encode some of it to make room
+            let n = self.encoder.poll(&mut self.output[self.written..]);
+            self.written += n;
+            if let Some(err) = self.encoder.error() {
+                return Err(err);
+            }
+            if n == 0 && self.written == self.output.len() {
+                self.full = true;
+                return Err(EncodeError::OutputFull);
+            }
+        }
+        Ok(())
+    }
+
+    /// Compresses the rest of the pushed data, returning all of the
+    /// compressed data
+    pub fn finish(mut self) -> Result<&'o [u8], EncodeError> {
+        while !self.full && !self.encoder.finish() {
+            let n = self.encoder.poll(&mut self.output[self.written..]);
+            self.written += n;
+            self.full = n == 0;
+        }
+        if let Some(err) = self.encoder.error() {
+            return Err(err);
+        }
+        if self.full {
+            return Err(EncodeError::OutputFull);
+        }
+        let output: &'o [u8] = self.output;
+        Ok(&output[..self.written])
+    }
+}
+
+impl<B: AsMut<[u8]>> Extend<u8> for PushEncoder<'_, B> {
+    fn extend<I: IntoIterator<Item = u8>>(&mut self, iter: I) {
+        for byte in iter {
+            if self.push(byte).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{PushEncoder, StreamEncoder};
+    use crate::{decode, encode, encode_with_options, Config, EncodeError, EncodeOptions};
+
+    fn stream_encode<'a>(
+        src: &[u8],
+        dst: &'a mut [u8],
+        chunk: usize,
+        cfg: &Config,
+        opts: &EncodeOptions,
+    ) -> &'a [u8] {
+        let mut buffer = [0; 3 << 11];
+        let mut enc = StreamEncoder::with_options(&mut buffer[..], cfg, opts);
+        let mut total = 0;
+        let mut poll = |enc: &mut StreamEncoder<&mut [u8]>| loop {
+            let end = dst.len().min(total + chunk);
+            let n = enc.poll(&mut dst[total..end]);
+            total += n;
+            if n == 0 {
+                break;
+            }
+        };
+        for part in src.chunks(chunk) {
+            let mut consumed = 0;
+            while consumed < part.len() {
+                consumed += enc.sink(&part[consumed..]);
+                poll(&mut enc);
+            }
+        }
+        while !enc.finish() {
+            poll(&mut enc);
+        }
+        assert_eq!(enc.input_consumed(), src.len());
+        assert_eq!(enc.output_produced(), total);
+        &dst[..total]
+    }
+
+    fn sample() -> [u8; 5000] {
+        let mut src = [0; 5000];
+        for (i, b) in src.iter_mut().enumerate() {
+            *b = (i * i % 251 % 13) as u8;
+        }
+        src
+    }
+
+    #[test]
+    fn matches_one_shot() {
+        let src = sample();
+        let mut dst1 = [0; 6000];
+        let mut dst2 = [0; 6000];
+        for cfg in [Config::new(11, 4).unwrap(), Config::new(8, 3).unwrap()] {
+            let expected = encode(&src, &mut dst1, &cfg).unwrap();
+            for chunk in [1, 3, 100, 5000] {
+                let encoded = stream_encode(&src, &mut dst2, chunk, &cfg, &Default::default());
+                assert_eq!(encoded, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn lazy_matches_one_shot() {
+        let src = sample();
+        let mut dst1 = [0; 6000];
+        let mut dst2 = [0; 6000];
+        let opts = EncodeOptions::new().with_lazy(true);
+        let cfg = Config::new(8, 3).unwrap();
+        let expected = encode_with_options(&src, &mut dst1, &cfg, &opts).unwrap();
+        for chunk in [1, 7, 5000] {
+            assert_eq!(stream_encode(&src, &mut dst2, chunk, &cfg, &opts), expected);
+        }
+        // The smallest possible buffer must not stall the encoder
+        let mut buffer = [0; (1 << 8) + (1 << 3) + 1];
+        let mut enc = StreamEncoder::with_options(&mut buffer[..], &cfg, &opts);
+        let mut consumed = 0;
+        let mut total = 0;
+        while consumed < src.len() {
+            consumed += enc.sink(&src[consumed..]);
+            total += enc.poll(&mut dst2[total..]);
+        }
+        while !enc.finish() {
+            total += enc.poll(&mut dst2[total..]);
+        }
+        assert_eq!(&dst2[..total], expected);
+    }
+
+    #[test]
+    fn cross_chunk_match() {
+        let mut src = [0; 64];
+        for (i, b) in src.iter_mut().enumerate() {
+            *b = b"0123456789"[i % 10];
+        }
+        let cfg = Config::new(8, 4).unwrap();
+        let mut dst1 = [0; 100];
+        let mut dst2 = [0; 100];
+        let expected = encode(&src, &mut dst1, &cfg).unwrap();
+        // The match of the repeated digits starting at byte 10 spans the split
+        let mut buffer = [0; 512];
+        let mut enc = StreamEncoder::new(&mut buffer[..], &cfg);
+        assert_eq!(enc.sink(&src[..25]), 25);
+        let mut total = enc.poll(&mut dst2);
+        assert_eq!(enc.sink(&src[25..]), src.len() - 25);
+        while !enc.finish() {
+            total += enc.poll(&mut dst2[total..]);
+        }
+        assert_eq!(&dst2[..total], expected);
+    }
+
+    #[test]
+    fn reset_between_frames() {
+        let src = sample();
+        let cfg = Config::new(8, 4).unwrap();
+        let mut buffer = [0; 1024];
+        let mut enc = StreamEncoder::new(&mut buffer[..], &cfg);
+        let mut dst1 = [0; 1000];
+        let mut dst2 = [0; 1000];
+        for frame in src.chunks(700) {
+            let mut consumed = 0;
+            let mut total = 0;
+            while consumed < frame.len() {
+                consumed += enc.sink(&frame[consumed..]);
+                total += enc.poll(&mut dst1[total..]);
+            }
+            while !enc.finish() {
+                total += enc.poll(&mut dst1[total..]);
+            }
+            assert_eq!(&dst1[..total], encode(frame, &mut dst2, &cfg).unwrap());
+            enc.reset();
+        }
+    }
+
+    #[test]
+    fn bounded_steps() {
+        let src = sample();
+        let mut dst1 = [0; 6000];
+        let mut dst2 = [0; 6000];
+        for cfg in [Config::new(8, 4).unwrap(), Config::new(2, 1).unwrap()] {
+            let expected = encode(&src, &mut dst1, &cfg).unwrap();
+            let lookahead = 1 << cfg.lookahead_sz2();
+            for max_symbols in [1, 2, 10] {
+                let mut buffer = [0; 1024];
+                let mut enc = StreamEncoder::new(&mut buffer[..], &cfg);
+                let mut total = 0;
+                loop {
+                    enc.sink(&src[enc.input_consumed()..]);
+                    if enc.input_consumed() == src.len() {
+                        enc.finish();
+                    }
+                    let head = enc.head;
+                    let n = enc.step(&mut dst2[total..], max_symbols);
+                    // Every symbol covers at most a lookahead of input
+                    assert!(enc.head - head <= (max_symbols + 1) * lookahead);
+                    total += n;
+                    if n == 0 {
+                        break;
+                    }
+                }
+                assert!(enc.finish());
+                assert_eq!(&dst2[..total], expected);
+            }
+        }
+    }
+
+    #[test]
+    fn push() {
+        let src = sample();
+        let mut dst1 = [0; 6000];
+        let mut dst2 = [0; 6000];
+        for cfg in [Config::new(8, 4).unwrap(), Config::new(2, 1).unwrap()] {
+            let expected = encode(&src, &mut dst1, &cfg).unwrap();
+            let mut buffer = [0; 300];
+            let mut enc = PushEncoder::new(&mut buffer[..], &mut dst2, &cfg);
+            for b in src {
+                enc.push(b).unwrap();
+            }
+            assert_eq!(enc.finish().unwrap(), expected);
+            let mut enc = PushEncoder::new(&mut buffer[..], &mut dst2, &cfg);
+            enc.extend(src.iter().copied());
+            assert_eq!(enc.finish().unwrap(), expected);
+            // Running out of output fails the push that needs the room, or
+            // the finish if none does
+            let mut small = [0; 100];
+            let mut enc = PushEncoder::new(&mut buffer[..], &mut small, &cfg);
+            let pushed = src.iter().take_while(|b| enc.push(**b).is_ok()).count();
+            assert!(pushed < src.len());
+            assert_eq!(enc.push(0), Err(EncodeError::OutputFull));
+            assert_eq!(enc.finish(), Err(EncodeError::OutputFull));
+            let len = expected.len();
+            let mut exact = [0; 6000];
+            let mut enc = PushEncoder::new(&mut buffer[..], &mut exact[..len - 1], &cfg);
+            enc.extend(src.iter().copied());
+            assert_eq!(enc.finish(), Err(EncodeError::OutputFull));
+            let mut enc = PushEncoder::new(&mut buffer[..], &mut exact[..len], &cfg);
+            enc.extend(src.iter().copied());
+            assert_eq!(enc.finish().unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn partial_flush() {
+        let src = sample();
+        let cfg = Config::new(8, 4).unwrap();
+        let mut buffer = [0; 1024];
+        let mut enc = StreamEncoder::new(&mut buffer[..], &cfg);
+        let mut dst1 = [0; 6000];
+        let mut dst2 = [0; 6000];
+        let mut total = 0;
+        for part in src.chunks(700) {
+            let mut consumed = 0;
+            while consumed < part.len() {
+                consumed += enc.sink(&part[consumed..]);
+                total += enc.poll(&mut dst1[total..]);
+            }
+            // Without the flush, a lookahead of input is held back
+            enc.partial_flush();
+            total += enc.poll(&mut dst1[total..]);
+            assert!(enc.head == enc.filled && enc.num_bits < 8);
+            let decoded = decode(&dst1[..total], &mut dst2, &cfg).unwrap();
+            let sunk = enc.input_consumed();
+            assert!(decoded.len() <= sunk && decoded.len() + 16 >= sunk);
+            assert_eq!(decoded, &src[..decoded.len()]);
+        }
+        while !enc.finish() {
+            total += enc.poll(&mut dst1[total..]);
+        }
+        assert_eq!(decode(&dst1[..total], &mut dst2, &cfg).unwrap(), src);
+    }
+
+    #[test]
+    fn empty_input() {
+        let mut dst = [0; 10];
+        let cfg = Config::default();
+        assert!(stream_encode(&[], &mut dst, 1, &cfg, &Default::default()).is_empty());
+    }
+
+    #[test]
+    #[cfg(not(debug_assertions))]
+    fn emit_out_of_range() {
+        let mut buffer = [0; 300];
+        let mut dst = [0; 10];
+        let mut enc = StreamEncoder::new(&mut buffer[..], &Config::new(8, 4).unwrap());
+        assert_eq!(enc.sink(b"abc"), 3);
+        enc.emit_bits(0x10, 4);
+        assert_eq!(enc.error(), Some(EncodeError::Internal));
+        assert_eq!(enc.sink(b"abc"), 0);
+        assert_eq!(enc.poll(&mut dst), 0);
+        assert!(enc.finish());
+        enc.reset();
+        assert_eq!(enc.error(), None);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic]
+    fn emit_out_of_range_asserts() {
+        let mut buffer = [0; 300];
+        let mut enc = StreamEncoder::new(&mut buffer[..], &Config::new(8, 4).unwrap());
+        enc.emit_bits(0x10, 4);
+    }
+}
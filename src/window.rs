@@ -0,0 +1,106 @@
+use super::Config;
+
+/// Ring buffer holding the last `1 << window_sz2` bytes of a stream, which
+/// is what back-references are resolved against. `StreamDecoder` keeps its
+/// history in one, and it serves for building other streaming layers.
+///
+/// The storage is provided by the caller, and only needs to implement
+/// `AsMut<[u8]>` like for the streaming codecs, which is why reading
+/// takes `&mut self` as well. The window starts out filled with zeros,
+/// matching the empty window of the C implementation
+pub struct Window<B> {
+    buf: B,
+    mask: usize,
+    pushed: usize, // Total number of bytes pushed
+}
+
+impl<B: AsMut<[u8]>> Window<B> {
+    /// Creates a window of `1 << window_sz2` bytes in `buf`, which is cleared
+    ///
+    /// # Panics
+    ///
+    /// Panics if `buf` is shorter than `1 << window_sz2` bytes
+    pub fn new(mut buf: B, cfg: &Config) -> Self {
+        let size = 1 << cfg.window_sz2 as usize;
+        assert!(buf.as_mut().len() >= size, "Window buffer is too small");
+        let mut window = Window {
+            buf,
+            mask: size - 1,
+            pushed: 0,
+        };
+        window.clear();
+        window
+    }
+
+    /// Empties the window, filling it with zeros as when created
+    pub fn clear(&mut self) {
+        let size = self.size();
+        self.buf.as_mut()[..size].fill(0);
+        self.pushed = 0;
+    }
+
+    /// Returns the size of the window
+    pub fn size(&self) -> usize {
+        self.mask + 1
+    }
+
+    /// Returns the total number of bytes pushed, which may exceed the size
+    pub fn pushed(&self) -> usize {
+        self.pushed
+    }
+
+    /// Appends `byte`, dropping the oldest byte once the window is full
+    pub fn push(&mut self, byte: u8) {
+        self.buf.as_mut()[self.pushed & self.mask] = byte;
+        self.pushed += 1;
+    }
+
+    /// Returns the byte `distance` bytes back, where 1 is the byte pushed
+    /// last. Distances beyond the bytes pushed so far read the initial
+    /// zeros. The distance must be between 1 and the size of the window
+    pub fn get_back(&mut self, distance: usize) -> u8 {
+        debug_assert!(distance >= 1 && distance <= self.size());
+        self.buf.as_mut()[self.pushed.wrapping_sub(distance) & self.mask]
+    }
+
+    /// Iterates over the bytes in the window from oldest to newest, which
+    /// are the last `size()` bytes pushed, or all of them if fewer
+    pub fn iter(&mut self) -> impl Iterator<Item = u8> + '_ {
+        let count = self.pushed.min(self.size());
+        let start = self.pushed - count;
+        let mask = self.mask;
+        let buf: &[u8] = self.buf.as_mut();
+        (start..self.pushed).map(move |pos| buf[pos & mask])
+    }
+
+    /// Releases the storage
+    pub fn into_inner(self) -> B {
+        self.buf
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Window;
+    use crate::Config;
+
+    #[test]
+    fn wraparound() {
+        let cfg = Config::new(4, 3).unwrap();
+        let mut window = Window::new([0xaa; 20], &cfg);
+        assert_eq!(window.size(), 16);
+        assert_eq!(window.iter().count(), 0);
+        assert_eq!(window.get_back(16), 0);
+        for byte in 0..20 {
+            window.push(byte);
+        }
+        assert_eq!(window.pushed(), 20);
+        assert_eq!(window.get_back(1), 19);
+        assert_eq!(window.get_back(16), 4);
+        assert!(window.iter().eq(4..20));
+        window.clear();
+        assert_eq!((window.pushed(), window.get_back(1)), (0, 0));
+        // Bytes beyond the window are left alone
+        assert_eq!(window.into_inner()[16..], [0xaa; 4]);
+    }
+}